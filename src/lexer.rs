@@ -1,4 +1,7 @@
-use std::io::{BufReader, Read};
+use std::{
+	fmt::Display,
+	io::{BufReader, Read, Write},
+};
 
 use crate::common::Location;
 
@@ -11,6 +14,22 @@ pub enum TokenType {
 	Identifier,
 	SpecialInstruction,
 	Operator,
+	LineReturn,
+}
+
+impl Display for TokenType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			TokenType::IntegerLiteral => "integer literal",
+			TokenType::StringLiteral => "string literal",
+			TokenType::BoolLiteral => "boolean literal",
+			TokenType::Keyword => "keyword",
+			TokenType::Identifier => "identifier",
+			TokenType::SpecialInstruction => "special instruction",
+			TokenType::Operator => "operator",
+			TokenType::LineReturn => "new line",
+		})
+	}
 }
 
 #[derive(Clone, Debug)]
@@ -43,7 +62,13 @@ enum LexerMode {
 	// Error,
 	Word,
 	Number,
-	String(bool, bool, bool),
+	String {
+		first: bool,
+		complete: bool,
+		escape: bool,
+		// Raw strings (`r"..."`) have no escape sequences
+		raw: bool,
+	},
 	Operator,
 }
 
@@ -79,6 +104,14 @@ where
 		self.next_char()?;
 
 		while self.curr_char.is_whitespace() {
+			if self.curr_char == '\n' {
+				return Ok(Token {
+					token_type: TokenType::LineReturn,
+					content: "\n".to_string(),
+					location: self.new_location(),
+				});
+			}
+
 			self.next_char()?;
 		}
 
@@ -90,7 +123,12 @@ where
 			} else if self.curr_char.is_numeric() {
 				LexerMode::Number
 			} else if self.curr_char == '"' {
-				LexerMode::String(true, false, false)
+				LexerMode::String {
+					first: true,
+					complete: false,
+					escape: false,
+					raw: false,
+				}
 			} else if is_operator(self.curr_char) {
 				LexerMode::Operator
 			} else {
@@ -104,16 +142,14 @@ where
 
 		loop {
 			let res = match mode {
-				LexerMode::Word => self.handle_word(&mut buff),
+				LexerMode::Word => self.handle_word(&mut buff, &mut mode),
 				LexerMode::Number => self.handle_number(&mut buff),
-				LexerMode::String(_, _, _) => self.handle_string(&mut buff, &mut mode),
+				LexerMode::String { .. } => self.handle_string(&mut buff, &mut mode),
 				LexerMode::Operator => self.handle_operator(&mut buff, &mut mode),
 			};
 
-			if let Result::Ok(complete) = res {
-				if complete {
-					break;
-				}
+			if res? {
+				break;
 			}
 
 			if let Result::Err(error) = self.next_char() {
@@ -131,7 +167,11 @@ where
 		let res = match mode {
 			LexerMode::Word => self.finalize_word(&buff),
 			LexerMode::Number => self.finalize_number(&buff),
-			LexerMode::String(_, _, _) => self.finalize_string(&buff),
+			// Only reached unterminated when the input ran out
+			LexerMode::String {
+				complete: false, ..
+			} => Err(Error::UnexpectedEndOfFile(self.curr_location.clone())),
+			LexerMode::String { .. } => self.finalize_string(&buff),
 			LexerMode::Operator => self.finalize_operator(&buff),
 		};
 
@@ -145,6 +185,9 @@ where
 					self.next_char()?;
 				}
 
+				// The comment's line return still terminates the line
+				self.buffered_char = Some(self.curr_char);
+
 				return self.next_token();
 			}
 			Ok(token)
@@ -153,9 +196,22 @@ where
 		}
 	}
 
-	fn handle_word(&mut self, buff: &mut String) -> Result<bool, Error> {
+	fn handle_word(&mut self, buff: &mut String, mode: &mut LexerMode) -> Result<bool, Error> {
 		let c = self.curr_char;
 
+		if buff == "r" && c == '"' {
+			buff.push(c);
+
+			*mode = LexerMode::String {
+				first: false,
+				complete: false,
+				escape: false,
+				raw: true,
+			};
+
+			return Ok(false);
+		}
+
 		if !buff.is_empty() && !c.is_alphanumeric() && c != '_' {
 			return Ok(true);
 		}
@@ -184,18 +240,24 @@ where
 	fn handle_string(&mut self, buff: &mut String, mode: &mut LexerMode) -> Result<bool, Error> {
 		let c = self.curr_char;
 
-		if let LexerMode::String(first, complete, escape) = mode {
+		if let LexerMode::String {
+			first,
+			complete,
+			escape,
+			raw,
+		} = mode
+		{
 			if *complete {
 				Ok(true)
-			} else if c != '"' || *escape || *first {
-				*first = false;
-				*escape = false;
+			} else if c == '\\' && !*escape && !*raw {
+				*escape = true;
 
 				buff.push(c);
 
 				Ok(false)
-			} else if c == '\\' {
-				*escape = true;
+			} else if c != '"' || *escape || *first {
+				*first = false;
+				*escape = false;
 
 				buff.push(c);
 
@@ -219,37 +281,22 @@ where
 			return Ok(false);
 		}
 
-		if buff.len() > 2 {
-			return Ok(true);
-		}
-
-		if buff.len() == 2 {
-			if buff == ">>" && c == '>' {
-				buff.push(c);
-				return Ok(false);
-			}
-			return Ok(true);
-		}
-
 		// TODO: handle more radix
-		if buff.starts_with('-') && c.is_numeric() {
+		if buff == "-" && c.is_numeric() {
 			buff.push(c);
 			*mode = LexerMode::Number;
 			return Ok(false);
 		}
 
-		if buff.starts_with(c) {
-			return match c {
-				'-' | '+' | '=' | '/' | '&' | '|' => {
-					buff.push(c);
-					Ok(false)
-				}
-				_ => Ok(true),
-			};
-		}
+		// Maximal munch: keep going as long as some operator starts with what was read
+		let mut candidate = buff.clone();
+		candidate.push(c);
 
-		if (buff.starts_with('>') || buff.starts_with('<')) && c == '=' {
-			buff.push(c);
+		if MULTI_CHAR_OPERATORS
+			.iter()
+			.any(|op| op.starts_with(candidate.as_str()))
+		{
+			*buff = candidate;
 			return Ok(false);
 		}
 
@@ -259,8 +306,14 @@ where
 	fn finalize_word(&mut self, buff: &str) -> Result<Token, Error> {
 		let tk_type = match buff {
 			"let" => TokenType::Keyword,
+			"const" => TokenType::Keyword,
 			"fn" => TokenType::Keyword,
 			"if" => TokenType::Keyword,
+			"else" => TokenType::Keyword,
+			"match" => TokenType::Keyword,
+			"loop" => TokenType::Keyword,
+			"break" => TokenType::Keyword,
+			"alias" => TokenType::Keyword,
 			"true" | "false" => TokenType::BoolLiteral,
 			// Not yet ready... SO DON'T YOU DARE USE IT YOU FILTHY MONSTER
 			"@include" => TokenType::SpecialInstruction,
@@ -370,20 +423,152 @@ where
 	}
 
 	fn new_location(&self) -> Location {
-		Location::new_z(self.line, self.col, self.curr_location.file().to_owned())
+		// `col` counts the characters read so far on the line, so it is already one-based
+		Location::new_z(
+			self.line,
+			self.col.saturating_sub(1),
+			self.curr_location.file().to_owned(),
+		)
 	}
 }
 
+/// Writes every token of `lexer` as `line:col Type "content"`, one per line, until EOF.
+pub fn dump_tokens<T: Read>(mut lexer: Lexer<T>, out: &mut impl Write) -> Result<(), Error> {
+	loop {
+		let token = match lexer.next_token() {
+			Ok(token) => token,
+			Err(Error::EndOfFile) => return Ok(()),
+			Err(err) => return Err(err),
+		};
+
+		let _ = writeln!(
+			out,
+			"{}:{} {:?} {:?}",
+			token.location.line(),
+			token.location.column(),
+			token.token_type,
+			token.content
+		);
+	}
+}
+
+// Operators made of several `is_operator` characters. As they are read one character at a time,
+// every prefix of an operator listed here must itself be a listed operator or a single character.
+const MULTI_CHAR_OPERATORS: &[&str] = &[
+	"==", "!=", "<=", ">=", "=>", "->", "<<", ">>", ">>>", "++", "--", "+=", "-=", "*=", "/=",
+	"**", "..", "..=",
+	// There is no `&&=` nor `|||`: those lex as `&&` `=` and `||` `|`
+	"&&", "||",
+];
+
 // TODO: Lexer: probably more operators?
 fn is_operator(c: char) -> bool {
 	match c {
-		'=' | '(' | ')' | ';' | '#' | ',' | '{' | '}' | '[' | ']'
-		// '+' | '-' | '*' | '/'
-			// | '.'
-			// | '>' | '<' | '|' | '&'
-			// | '?' | ':'
-			// | ';' | '(' | ')' | '[' | ']' | '{' | '}'
+		'=' | '(' | ')' | ';' | '#' | ',' | '{' | '}' | '[' | ']' | '-' | '>' | '<' | '!' | '.'
+			| ':' | '+' | '*' | '/' | '&' | '|'
+			// | '?'
 			=> true,
 		_ => false
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::{dump_tokens, Error, Lexer, Token, TokenType, MULTI_CHAR_OPERATORS};
+
+	fn dump(source: &str) -> String {
+		let mut out = Vec::new();
+
+		dump_tokens(
+			Lexer::new(Cursor::new(source.to_string()), "test".into()),
+			&mut out,
+		)
+		.expect("test source should lex");
+
+		String::from_utf8(out).unwrap()
+	}
+
+	fn tokens(source: &str) -> Vec<Token> {
+		let mut lexer = Lexer::new(Cursor::new(source.to_string()), "test".into());
+		let mut tokens = Vec::new();
+
+		loop {
+			match lexer.next_token() {
+				Ok(token) => tokens.push(token),
+				Err(Error::EndOfFile) => return tokens,
+				Err(err) => panic!("{source:?} should lex: {err:?}"),
+			}
+		}
+	}
+
+	// The content of every token, all of which must be operators
+	fn operators(source: &str) -> Vec<String> {
+		tokens(source)
+			.into_iter()
+			.map(|token| {
+				assert_eq!(token.token_type, TokenType::Operator, "in {source:?}");
+				token.content
+			})
+			.collect()
+	}
+
+	#[test]
+	fn token_dump_snapshot() {
+		assert_eq!(
+			dump("let x = -12; # comment\nprintln(\"a b\", x >= 3);"),
+			concat!(
+				"1:1 Keyword \"let\"\n",
+				"1:5 Identifier \"x\"\n",
+				"1:7 Operator \"=\"\n",
+				"1:9 IntegerLiteral \"-12\"\n",
+				"1:12 Operator \";\"\n",
+				"2:1 LineReturn \"\\n\"\n",
+				"2:1 Identifier \"println\"\n",
+				"2:8 Operator \"(\"\n",
+				"2:9 StringLiteral \"\\\"a b\\\"\"\n",
+				"2:14 Operator \",\"\n",
+				"2:16 Identifier \"x\"\n",
+				"2:18 Operator \">=\"\n",
+				"2:21 IntegerLiteral \"3\"\n",
+				"2:22 Operator \")\"\n",
+				"2:23 Operator \";\"\n",
+			)
+		);
+	}
+
+	#[test]
+	fn every_operator() {
+		for op in "=();,{}[]-><!.:+*/&|".chars() {
+			assert_eq!(operators(&op.to_string()), [op.to_string()]);
+		}
+
+		for op in MULTI_CHAR_OPERATORS {
+			assert_eq!(operators(op), [op.to_string()]);
+		}
+
+		// The longest operator wins, and what follows starts a new one
+		assert_eq!(operators(">>>="), [">>>", "="]);
+		assert_eq!(operators("<<="), ["<<", "="]);
+		assert_eq!(operators("..=="), ["..=", "="]);
+		assert_eq!(operators("=>=="), ["=>", "=="]);
+		assert_eq!(operators("(-)"), ["(", "-", ")"]);
+		assert_eq!(operators("= ="), ["=", "="]);
+	}
+
+	#[test]
+	fn logical_operators() {
+		let contents: Vec<String> = tokens("a && b || c")
+			.into_iter()
+			.map(|token| token.content)
+			.collect();
+		assert_eq!(contents, ["a", "&&", "b", "||", "c"]);
+
+		assert_eq!(operators("&&||"), ["&&", "||"]);
+		assert_eq!(operators("&&="), ["&&", "="]);
+		assert_eq!(operators("|||"), ["||", "|"]);
+		assert_eq!(operators("&&&"), ["&&", "&"]);
+		assert_eq!(operators("&|"), ["&", "|"]);
+	}
+}
@@ -0,0 +1,10 @@
+//! Lexer, parser and VM of the ulesl scripting language, for the `ulesl` binary and for hosts
+//! embedding the interpreter.
+
+#[macro_use]
+pub mod trace;
+
+pub mod common;
+pub mod lexer;
+pub mod parser;
+pub mod vm;
@@ -1,69 +1,397 @@
-mod common;
-mod lexer;
-mod parser;
-mod vm;
-use std::io::{IsTerminal, Read, Write};
+use std::{
+	any::Any,
+	io::{IsTerminal, Read, Write},
+	panic::Location,
+};
 
-use lexer::Lexer;
-use parser::Parser;
-
-use crate::vm::Vm;
+use easy_scripting_language::{
+	lexer::{self, Lexer},
+	parser::Parser,
+	trace,
+	vm::Vm,
+};
 
 fn main() {
-	// println!("[VM DEBUG] Hello, world!");
+	let mut input_path: Option<String> = None;
+	let mut dump_tokens = false;
+	let mut dump_ast = false;
+	let mut check_arities = false;
+	let mut script_args: Vec<String> = Vec::new();
 
-	let mut args: Vec<String> = std::env::args().skip(1).collect();
+	for arg in std::env::args().skip(1) {
+		// Everything after the script path belongs to the script
+		if input_path.is_some() {
+			script_args.push(arg);
+			continue;
+		}
 
-	if args.len() > 1 {
-		eprintln!("ulesl: Too many arguments");
+		match arg.as_str() {
+			"--tokens" => dump_tokens = true,
+			"--ast" => dump_ast = true,
+			"--check" => check_arities = true,
+			"--trace" => trace::set_enabled(true),
+			_ => input_path = Some(arg),
+		}
 	}
 
-	let (reader, file, interactive): (Box<dyn Read>, String, bool) =
-		if args.is_empty() || args[0] == "-" {
-			(
-				Box::new(std::io::stdin()),
-				"stdin".into(),
-				std::io::stdin().is_terminal(),
-			)
-		} else {
-			(
-				Box::new(std::fs::File::open(&args[0]).expect("ulesl: Could not open input file")),
-				args.pop().unwrap(),
-				false,
-			)
-		};
-
-	let lex = Lexer::new(reader, file);
-	// let mut parser = Parser::new(lex, "test.ulesl".into());
-	let mut parser = Parser::new(lex, "test.ulesl".into());
-	let mut vm = Vm::new();
+	if std::env::var_os("ULESL_TRACE").is_some_and(|v| !v.is_empty() && v != "0") {
+		trace::set_enabled(true);
+	}
 
-	vm.register_default_builtins();
+	install_panic_hook();
 
-	loop {
-		if interactive {
-			print!("ulesl> ");
-			let _ = std::io::stdout().flush();
+	let source_is_stdin = !matches!(input_path.as_deref(), Some(path) if path != "-");
+
+	let (reader, file, interactive): (Box<dyn Read>, String, bool) = match input_path {
+		Some(path) if path != "-" => match std::fs::File::open(&path) {
+			Ok(file) => (Box::new(file), path, false),
+			Err(err) => {
+				eprintln!("ulesl: could not open {path}: {err}");
+				std::process::exit(1);
+			}
+		},
+		_ => (
+			Box::new(std::io::stdin()),
+			"stdin".into(),
+			std::io::stdin().is_terminal(),
+		),
+	};
+
+	let lex = Lexer::new(reader, file.clone());
+
+	if dump_tokens {
+		if let Err(err) = lexer::dump_tokens(lex, &mut std::io::stdout().lock()) {
+			eprintln!("{err}");
+		}
+
+		return;
+	}
+
+	let mut parser = Parser::new(lex, file);
+
+	if dump_ast {
+		match parser.parse_all() {
+			Ok(packages) => println!("{packages:#?}"),
+			Err(err) => eprintln!("{err}"),
 		}
 
-		match parser.next_package() {
-			Ok(Some(p)) => {
-				// println!("[VM DEBUG] Parsed package: {p:?}");
+		return;
+	}
+
+	let mut vm = new_vm(script_args.clone(), source_is_stdin);
 
-				if let Err(err) = vm.exec_package(p) {
-					eprintln!("Vm error: {err}");
+	if !interactive {
+		let (packages, errors) = parser.parse_all_with_recovery();
+
+		if !errors.is_empty() {
+			for err in errors {
+				vm.report_error(err);
+			}
+
+			std::process::exit(1);
+		}
+
+		// Report every wrong call up front instead of stopping at the first one executed
+		if check_arities {
+			let mismatches = vm.check_arities(&packages);
+
+			if !mismatches.is_empty() {
+				for mismatch in mismatches {
+					vm.report_error(mismatch);
 				}
+
+				std::process::exit(1);
 			}
-			Ok(None) => {
-				// println!("[VM DEBUG] EOF reached!");
+		}
+
+		let mut failed = false;
+
+		for p in packages {
+			trace!("parsed package: {p:?}");
+
+			if let Err(err) = vm.exec_package(p) {
+				if let Some(code) = err.exit_code() {
+					// Exiting skips destructors, and dropping the VM kills what it spawned
+					drop(vm);
+					std::process::exit(code);
+				}
+
+				vm.report_error(format_args!("Vm error: {err}"));
+				failed = true;
+			}
+		}
+
+		// `exit` takes precedence, then any error makes the script fail
+		if failed {
+			drop(vm);
+			std::process::exit(1);
+		}
+
+		return;
+	}
+
+	// The REPL reads whole lines itself so it can intercept `:commands` before the parser
+	let mut source = String::new();
+
+	loop {
+		print!(
+			"{}",
+			if source.is_empty() {
+				"ulesl> "
+			} else {
+				"...... "
+			}
+		);
+		let _ = std::io::stdout().flush();
+
+		let mut line = String::new();
+
+		match std::io::stdin().read_line(&mut line) {
+			Ok(0) => {
+				trace!("end of input");
 				break;
 			}
+			Ok(_) => (),
 			Err(err) => {
-				eprintln!("{err}");
-				if !interactive {
+				vm.report_error(err);
+				break;
+			}
+		}
+
+		if source.is_empty() {
+			if let Some(command) = line.trim().strip_prefix(':') {
+				if !run_repl_command(&mut vm, command, &script_args, source_is_stdin) {
 					break;
 				}
+
+				continue;
+			}
+		}
+
+		source.push_str(&line);
+
+		// Keep reading while a block, array or call is still open
+		if !is_complete_input(&source) {
+			continue;
+		}
+
+		let lex = Lexer::new(
+			std::io::Cursor::new(std::mem::take(&mut source)),
+			"stdin".into(),
+		);
+		let (packages, errors) = Parser::new(lex, "stdin".into()).parse_all_with_recovery();
+
+		for err in errors {
+			vm.report_error(err);
+		}
+
+		for p in packages {
+			trace!("parsed package: {p:?}");
+
+			if let Err(err) = vm.exec_package(p) {
+				if let Some(code) = err.exit_code() {
+					drop(vm);
+					std::process::exit(code);
+				}
+
+				vm.report_error(format_args!("Vm error: {err}"));
 			}
 		}
 	}
 }
+
+// Internal panics are interpreter bugs, not script errors: say so instead of showing Rust's
+// raw message. Tracing keeps the default output (and its backtrace) for debugging.
+fn install_panic_hook() {
+	let default_hook = std::panic::take_hook();
+
+	std::panic::set_hook(Box::new(move |info| {
+		eprintln!("{}", panic_message(info.payload(), info.location()));
+
+		if trace::enabled() {
+			default_hook(info);
+		}
+	}));
+}
+
+// Takes the parts of a `PanicHookInfo`, which can't be built outside of a panic hook
+fn panic_message(payload: &(dyn Any + Send), location: Option<&Location>) -> String {
+	let payload = payload
+		.downcast_ref::<&str>()
+		.copied()
+		.or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+		.unwrap_or("unknown cause");
+
+	let error = match location {
+		Some(location) => format!("ulesl: internal interpreter error at {location}: {payload}"),
+		None => format!("ulesl: internal interpreter error: {payload}"),
+	};
+
+	format!("{error}\nThis is a bug, please report it.")
+}
+
+/// Runs a `:command` typed at the REPL prompt. Returns false when the REPL should stop.
+fn run_repl_command(
+	vm: &mut Vm,
+	command: &str,
+	script_args: &[String],
+	source_is_stdin: bool,
+) -> bool {
+	match command {
+		"reset" => *vm = new_vm(script_args.to_vec(), source_is_stdin),
+		"vars" => vm
+			.global_variable_names()
+			.iter()
+			.for_each(|n| println!("{n}")),
+		"funcs" => vm
+			.global_function_names()
+			.iter()
+			.for_each(|n| println!("{n}")),
+		"quit" => return false,
+		_ => vm.report_error(format_args!("unknown REPL command: :{command}")),
+	}
+
+	true
+}
+
+fn new_vm(script_args: Vec<String>, source_is_stdin: bool) -> Vm {
+	let mut vm = Vm::new();
+
+	vm.register_default_builtins();
+	vm.set_args(script_args);
+
+	// stdin is free for the script to read only when it isn't the source
+	if !source_is_stdin {
+		vm.set_input(Box::new(std::io::stdin()));
+	}
+
+	vm
+}
+
+/// Tells whether every `{`, `[` and `(` of the REPL input is closed, ignoring the contents of
+/// strings and comments.
+fn is_complete_input(source: &str) -> bool {
+	let mut depth = 0i32;
+	let mut in_string = false;
+	let mut raw = false;
+	let mut escaped = false;
+	let mut in_comment = false;
+	// The last two characters, to tell a raw string `r"` from a word ending with `r`
+	let mut prev = ['\0'; 2];
+
+	for c in source.chars() {
+		let [before_prev, last] = prev;
+		prev = [last, c];
+
+		if in_comment {
+			in_comment = c != '\n';
+			continue;
+		}
+
+		if in_string {
+			match c {
+				_ if escaped => escaped = false,
+				'\\' if !raw => escaped = true,
+				'"' => in_string = false,
+				_ => (),
+			}
+
+			continue;
+		}
+
+		match c {
+			'"' => {
+				in_string = true;
+				raw = last == 'r' && !(before_prev.is_alphanumeric() || before_prev == '_');
+			}
+			'#' => in_comment = true,
+			'{' | '[' | '(' => depth += 1,
+			'}' | ']' | ')' => depth -= 1,
+			_ => (),
+		}
+	}
+
+	!in_string && depth <= 0
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{io::Cursor, panic::Location};
+
+	use easy_scripting_language::{lexer::Lexer, parser::Parser, vm::Vm};
+
+	use super::{is_complete_input, new_vm, panic_message, run_repl_command};
+
+	fn exec(vm: &mut Vm, source: &str) {
+		let lexer = Lexer::new(Cursor::new(source.to_string()), "stdin".into());
+
+		for package in Parser::new(lexer, "stdin".into()).parse_all().unwrap() {
+			vm.exec_package(package).unwrap();
+		}
+	}
+
+	#[test]
+	fn reset_clears_definitions() {
+		let mut vm = new_vm(Vec::new(), true);
+
+		exec(&mut vm, "let x = 1;\nfn f() { x }");
+		assert!(vm.get_global("x").is_some());
+
+		assert!(run_repl_command(&mut vm, "reset", &[], true));
+		assert!(vm.get_global("x").is_none());
+		assert!(vm.global_function_names().is_empty());
+
+		// Builtins are still there
+		exec(&mut vm, "let x = typename(1);");
+		assert!(!run_repl_command(&mut vm, "quit", &[], true));
+	}
+
+	#[test]
+	fn complete_input() {
+		assert!(is_complete_input("println(1);"));
+		assert!(!is_complete_input("fn f() {"));
+		assert!(!is_complete_input("println([1,"));
+		assert!(is_complete_input("fn f() {\n}"));
+		assert!(!is_complete_input(r#"let s = "abc"#));
+		assert!(is_complete_input(r#"let s = "{ \" (";"#));
+	}
+
+	#[test]
+	fn complete_input_ignores_comments() {
+		assert!(is_complete_input("println(1); # close with }"));
+		assert!(is_complete_input("# ( [ {\nprintln(1);"));
+		assert!(!is_complete_input("fn f() { # }"));
+		assert!(!is_complete_input("fn f() { # }\nprintln(1);"));
+		assert!(is_complete_input(r##"let s = "#"; println(s);"##));
+	}
+
+	#[test]
+	fn complete_input_handles_raw_strings() {
+		// A raw string's backslash doesn't escape its closing quote
+		assert!(is_complete_input(r#"let p = r"C:\";"#));
+		assert!(!is_complete_input(r#"let p = r"C:\"; fn f() {"#));
+		// `er"` is an identifier followed by a regular string
+		assert!(!is_complete_input(r#"println(er"\");"#));
+	}
+
+	#[test]
+	fn panic_message_names_the_location() {
+		let location = Location::caller();
+		let prefix = format!("ulesl: internal interpreter error at {location}: ");
+		let suffix = "\nThis is a bug, please report it.";
+
+		// `panic!` gives a `&str` payload for literals and a `String` for formatted messages
+		assert_eq!(
+			panic_message(&"literal", Some(location)),
+			format!("{prefix}literal{suffix}")
+		);
+		assert_eq!(
+			panic_message(&format!("formatted {}", 1), Some(location)),
+			format!("{prefix}formatted 1{suffix}")
+		);
+		assert_eq!(
+			panic_message(&1, None),
+			format!("ulesl: internal interpreter error: unknown cause{suffix}")
+		);
+	}
+}
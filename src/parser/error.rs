@@ -1,8 +1,11 @@
-use std::num::ParseIntError;
+use std::num::{IntErrorKind, ParseIntError};
 
 use thiserror::Error;
 
-use crate::lexer::{self, Token};
+use crate::{
+	common::Location,
+	lexer::{self, Token},
+};
 
 pub type Result<T> = std::result::Result<T, ParserError>;
 
@@ -10,16 +13,34 @@ pub type Result<T> = std::result::Result<T, ParserError>;
 pub enum ParserError {
 	#[error("{0}")]
 	Lexer(#[from] lexer::Error),
-	#[error("{}: unexpected token \"{}\", expected: {1:?}", .0.location, .0.content)]
+	#[error("{}: unexpected token \"{}\"{}", .0.location, .0.content, expected_suffix(.1))]
 	UnexpectedToken(Token, Option<String>),
-	#[error("Invalid number: \"{0}\"")]
-	IntegerParsing(String, Option<ParseIntError>),
-	#[error("Unexpected End of File")]
-	UnexpectedEndOfFile,
+	#[error("{0}: Invalid number: \"{1}\"")]
+	IntegerParsing(Location, String, Option<ParseIntError>),
+	#[error("{0}: integer literal too large for i64: \"{1}\"")]
+	IntegerOverflow(Location, String),
+	#[error("{0}: comparison operators cannot be chained, compare each pair separately")]
+	ChainedComparison(Location),
+	#[error("{0}: Unexpected End of File")]
+	UnexpectedEndOfFile(Location),
 }
 
-impl From<(String, ParseIntError)> for ParserError {
-	fn from(value: (String, ParseIntError)) -> Self {
-		Self::IntegerParsing(value.0, Some(value.1))
+fn expected_suffix(expected: &Option<String>) -> String {
+	match expected {
+		Some(expected) => format!(", expected {expected}"),
+		None => String::new(),
+	}
+}
+
+impl From<(Token, ParseIntError)> for ParserError {
+	fn from(value: (Token, ParseIntError)) -> Self {
+		let (token, err) = value;
+
+		match err.kind() {
+			IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+				Self::IntegerOverflow(token.location, token.content)
+			}
+			_ => Self::IntegerParsing(token.location, token.content, Some(err)),
+		}
 	}
 }
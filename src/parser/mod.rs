@@ -1,20 +1,31 @@
-use std::io::Read;
+use std::{collections::VecDeque, io::Read};
 
 pub mod error;
 pub mod types;
 
 use types::*;
 
-use crate::lexer::{self, Lexer, Token, TokenType};
+use crate::{
+	common::Location,
+	lexer::{self, Lexer, Token, TokenType},
+};
 
 use self::error::{ParserError, Result};
 
+enum BlockItem {
+	Statement(LocatedType<ParsedHighLevel>),
+	Tail(LocatedType<Expr>),
+}
+
 pub struct Parser<T: Read> {
 	lexer: Lexer<T>,
 	source: String,
 	current_token: Option<Token>,
 	lookahead_token: Option<Token>,
-	retain_last_token: bool,
+	// Tokens read past the lookahead one by `peek_second_token`
+	pending_tokens: VecDeque<Token>,
+	// Where the last token read was, still known once the input runs out
+	last_location: Option<Location>,
 }
 
 impl<T: Read> Parser<T> {
@@ -24,11 +35,20 @@ impl<T: Read> Parser<T> {
 			source,
 			current_token: None,
 			lookahead_token: None,
-			retain_last_token: false,
+			pending_tokens: VecDeque::new(),
+			last_location: None,
 		}
 	}
 
 	pub fn next_package(&mut self) -> Result<Option<LocatedType<ParsedHighLevel>>> {
+		Ok(self.parse_block_item(false)?.map(|item| match item {
+			BlockItem::Statement(statement) => statement,
+			BlockItem::Tail(_) => unreachable!("tail expressions are only parsed inside blocks"),
+		}))
+	}
+
+	// Parses a statement or, when `tail_allowed`, the trailing expression of a block
+	fn parse_block_item(&mut self, tail_allowed: bool) -> Result<Option<BlockItem>> {
 		let peeked_token = self.peek_token()?;
 
 		let Some(token) = peeked_token else {
@@ -37,67 +57,241 @@ impl<T: Read> Parser<T> {
 
 		let location = token.location.clone();
 
-		self.expect_token_type(&token, TokenType::Identifier)
-			.or_else(|_| self.expect_token_type(&token, TokenType::Keyword))
-			.or_else(|_| self.expect_token(&token, TokenType::Operator, ";"))?;
-
-		let high_level = if let TokenType::Keyword = &token.token_type {
-			match token.content.as_str() {
-				"let" => ParsedHighLevel::VarDecl(self.parse_var_decl_or_assign()?),
+		let high_level = match token.token_type {
+			TokenType::Keyword => match token.content.as_str() {
+				"let" => ParsedHighLevel::VarDecl(self.parse_var_decl()?),
+				"const" => ParsedHighLevel::ConstDecl(self.parse_var_decl()?),
 				"fn" => ParsedHighLevel::FuncDecl(self.parse_func_decl()?),
-				"if" => ParsedHighLevel::If(self.parse_if_statement()?),
+				"if" => {
+					let if_statement = self.parse_if_statement()?;
+
+					// An if ending its block gives the block its value
+					if tail_allowed {
+						let next_token = self.peek_or_fail()?;
+
+						if TokenType::Operator == next_token.token_type && next_token.content == "}"
+						{
+							return Ok(Some(BlockItem::Tail(LocatedType::new(
+								Expr::If(Box::new(if_statement)),
+								location,
+							))));
+						}
+					}
+
+					ParsedHighLevel::If(if_statement)
+				}
+				"match" => ParsedHighLevel::Match(self.parse_match_statement()?),
+				"loop" => {
+					let block = self.parse_loop()?;
+
+					// Like an if, a loop ending its block gives the block its value
+					if tail_allowed {
+						let next_token = self.peek_or_fail()?;
+
+						if TokenType::Operator == next_token.token_type && next_token.content == "}"
+						{
+							return Ok(Some(BlockItem::Tail(LocatedType::new(
+								Expr::Loop(block),
+								location,
+							))));
+						}
+					}
+
+					ParsedHighLevel::Loop(block)
+				}
+				"break" => ParsedHighLevel::Break(self.parse_break()?),
+				"alias" => ParsedHighLevel::Alias(self.parse_alias()?),
 				_ => {
 					return self.unexpected_token(
-						self.current_token.clone().unwrap(),
+						token,
 						Some("statement or function declaration".to_string()),
 					)
 				}
+			},
+			TokenType::Operator if token.content == ";" => {
+				self.advance_token()?;
+
+				ParsedHighLevel::Noop
 			}
-		} else if let TokenType::Operator = &token.token_type {
-			self.advance_token()?;
+			// Map literals only appear in expressions, so a `{` starting a statement opens a block
+			TokenType::Operator if token.content == "{" && !tail_allowed => {
+				ParsedHighLevel::Block(self.parse_block()?)
+			}
+			// Inside a block, it may also start the block's tail, which can be a map
+			TokenType::Operator if token.content == "{" => {
+				let expr = self.parse_expr()?;
 
-			ParsedHighLevel::Noop
-		} else {
-			self.advance_token()?;
+				let next_token = self.peek_or_fail()?;
 
-			let disc_tk = self.peek_or_fail()?;
+				if TokenType::Operator == next_token.token_type && next_token.content == "}" {
+					return Ok(Some(BlockItem::Tail(LocatedType::new(expr, location))));
+				}
 
-			self.expect_token_type(&disc_tk, TokenType::Operator)?;
+				match expr {
+					Expr::Block(block) => ParsedHighLevel::Block(block),
+					// `{}` reads as an empty map until it turns out not to be the tail
+					Expr::Map(map) if map.entries.is_empty() => {
+						ParsedHighLevel::Block(StatementBlock {
+							statements: Vec::new(),
+							tail: None,
+						})
+					}
+					_ => return self.unexpected_token(next_token, Some("\"}\"".to_string())),
+				}
+			}
+			TokenType::Identifier => return self.parse_expr_statement(location, tail_allowed),
+			_ if tail_allowed => {
+				let expr = self.parse_expr()?;
 
-			match disc_tk.content.as_str() {
-				"(" => {
-					self.advance_token()?;
+				let block_end = self.peek_or_fail()?;
+				self.expect_token(&block_end, TokenType::Operator, "}")?;
 
-					let args = self.parse_expr_list(")")?;
+				return Ok(Some(BlockItem::Tail(LocatedType::new(expr, location))));
+			}
+			_ => return self.unexpected_token(token, Some("statement".to_string())),
+		};
 
-					let end_tk = self.next_or_fail()?;
-					self.expect_token(&end_tk, TokenType::Operator, ";")?;
+		Ok(Some(BlockItem::Statement(LocatedType::new(
+			high_level, location,
+		))))
+	}
 
-					ParsedHighLevel::FuncCall(FuncCallExpr {
-						name: token.content,
-						args,
-					})
-				}
-				"=" => {
-					self.retain_token();
-					ParsedHighLevel::VarSet(self.parse_var_decl_or_assign()?)
-				}
-				_ => self.unexpected_token(disc_tk, Some("( or =".to_string()))?,
+	// Parses an assignment or a function call statement. Inside a block, an expression directly
+	// followed by the closing `}` is the block's tail instead.
+	fn parse_expr_statement(
+		&mut self,
+		location: Location,
+		tail_allowed: bool,
+	) -> Result<Option<BlockItem>> {
+		let expr = self.parse_expr()?;
+
+		if let Expr::Assign(assign) = expr {
+			self.expect_terminator()?;
+
+			let high_level = ParsedHighLevel::VarSet(VarAssign {
+				name: assign.name,
+				val: *assign.val,
+			});
+
+			return Ok(Some(BlockItem::Statement(LocatedType::new(
+				high_level, location,
+			))));
+		}
+
+		// Looking for the `}` consumes the line returns before it, which terminate the statement
+		let mut terminated = false;
+
+		if tail_allowed {
+			terminated = self
+				.peek_token_raw()?
+				.is_some_and(|tk| tk.token_type == TokenType::LineReturn);
+
+			let next_token = self.peek_or_fail()?;
+
+			if TokenType::Operator == next_token.token_type && next_token.content == "}" {
+				return Ok(Some(BlockItem::Tail(LocatedType::new(expr, location))));
 			}
+		}
+
+		let Expr::FuncCall(call) = expr else {
+			let token = match self.peek_token_raw()? {
+				Some(token) if token.token_type != TokenType::LineReturn => token,
+				// UNWRAP: the expression has consumed at least one token
+				_ => self.current_token.clone().unwrap(),
+			};
+
+			return self.unexpected_token(token, Some("one of: ( or =".to_string()));
 		};
 
-		Ok(Some(LocatedType::new(high_level, location)))
+		if !terminated {
+			self.expect_terminator()?;
+		}
+
+		Ok(Some(BlockItem::Statement(LocatedType::new(
+			ParsedHighLevel::FuncCall(call),
+			location,
+		))))
 	}
 
-	fn parse_var_decl_or_assign(&mut self) -> Result<VarAssign> {
-		let next_tk = self.next_or_fail()?;
+	/// Parses every remaining package, stopping at the first error.
+	pub fn parse_all(&mut self) -> Result<Vec<LocatedType<ParsedHighLevel>>> {
+		let mut packages = Vec::new();
 
-		let name_tk = if next_tk.content == "let" {
-			self.next_or_fail()?
-		} else {
-			next_tk
+		while let Some(package) = self.next_package()? {
+			packages.push(package);
+		}
+
+		Ok(packages)
+	}
+
+	/// Parses every remaining package. On a parse error, skips to the next `;` or `}` and
+	/// carries on, so that several independent errors can be reported at once.
+	pub fn parse_all_with_recovery(
+		&mut self,
+	) -> (Vec<LocatedType<ParsedHighLevel>>, Vec<ParserError>) {
+		let mut packages = Vec::new();
+		let mut errors = Vec::new();
+
+		loop {
+			match self.next_package() {
+				Ok(Some(package)) => packages.push(package),
+				Ok(None) => break,
+				Err(err) => {
+					let eof = matches!(
+						err,
+						ParserError::UnexpectedEndOfFile(_)
+							| ParserError::Lexer(lexer::Error::UnexpectedEndOfFile(_))
+					);
+
+					errors.push(err);
+
+					if eof || !self.skip_to_sync_token() {
+						break;
+					}
+				}
+			}
+		}
+
+		(packages, errors)
+	}
+
+	// Returns false if EOF was reached before finding a `;`, a `}` or a line return
+	fn skip_to_sync_token(&mut self) -> bool {
+		let is_sync_token = |tk: &Token| match tk.token_type {
+			TokenType::Operator => tk.content == ";" || tk.content == "}",
+			TokenType::LineReturn => true,
+			_ => false,
 		};
 
+		// The failing token was the statement's terminator and has already been consumed
+		if self.lookahead_token.is_none() && self.current_token.as_ref().is_some_and(is_sync_token)
+		{
+			return true;
+		}
+
+		loop {
+			let token = match self.peek_token_raw() {
+				Ok(Some(token)) => token,
+				Ok(None) => return false,
+				Err(ParserError::Lexer(lexer::Error::UnexpectedEndOfFile(_))) => return false,
+				// Keep skipping past invalid characters
+				Err(_) => continue,
+			};
+
+			self.lookahead_token = None;
+
+			if is_sync_token(&token) {
+				return true;
+			}
+		}
+	}
+
+	fn parse_var_decl(&mut self) -> Result<VarAssign> {
+		// Skip the `let` or `const` keyword
+		self.advance_token()?;
+
+		let name_tk = self.next_or_fail()?;
+
 		self.expect_token_type(&name_tk, TokenType::Identifier)?;
 
 		let assign_tk = self.next_or_fail()?;
@@ -106,8 +300,7 @@ impl<T: Read> Parser<T> {
 
 		let val = self.parse_expr()?;
 
-		let end_tk = self.next_or_fail()?;
-		self.expect_token(&end_tk, TokenType::Operator, ";")?;
+		self.expect_terminator()?;
 
 		Ok(VarAssign {
 			name: name_tk.content,
@@ -115,6 +308,27 @@ impl<T: Read> Parser<T> {
 		})
 	}
 
+	fn parse_alias(&mut self) -> Result<AliasDecl> {
+		let alias_keyword = self.next_or_fail()?;
+		self.expect_token(&alias_keyword, TokenType::Keyword, "alias")?;
+
+		let name_tk = self.next_or_fail()?;
+		self.expect_token_type(&name_tk, TokenType::Identifier)?;
+
+		let assign_tk = self.next_or_fail()?;
+		self.expect_token(&assign_tk, TokenType::Operator, "=")?;
+
+		let target_tk = self.next_or_fail()?;
+		self.expect_token_type(&target_tk, TokenType::Identifier)?;
+
+		self.expect_terminator()?;
+
+		Ok(AliasDecl {
+			name: name_tk.content,
+			target: target_tk.content,
+		})
+	}
+
 	fn parse_func_decl(&mut self) -> Result<FuncDecl> {
 		let fn_keyword = self.next_or_fail()?;
 		self.expect_token(&fn_keyword, TokenType::Keyword, "fn")?;
@@ -127,50 +341,272 @@ impl<T: Read> Parser<T> {
 
 		self.expect_token(&parenth, TokenType::Operator, "(")?;
 
-		let arg_list = self.parse_arg_list(")")?;
+		let (args, arg_types) = self.parse_arg_list(")")?.into_iter().unzip();
+		let ret_type = self.parse_type_annotation()?;
 		let block = self.parse_block()?;
 
 		Ok(FuncDecl {
 			name: func_identifier.content,
-			args: arg_list,
+			args,
+			arg_types,
+			ret_type,
 			block,
 		})
 	}
 
+	fn parse_loop(&mut self) -> Result<StatementBlock> {
+		let loop_keyword = self.next_or_fail()?;
+		self.expect_token(&loop_keyword, TokenType::Keyword, "loop")?;
+
+		self.parse_block()
+	}
+
+	// `break` alone, or followed by the value the loop evaluates to
+	fn parse_break(&mut self) -> Result<Option<Expr>> {
+		let break_keyword = self.next_or_fail()?;
+		self.expect_token(&break_keyword, TokenType::Keyword, "break")?;
+
+		let has_value = self
+			.peek_token_raw()?
+			.is_some_and(|tk| match tk.token_type {
+				TokenType::LineReturn => false,
+				TokenType::Operator => tk.content != ";" && tk.content != "}",
+				_ => true,
+			});
+
+		let value = if has_value {
+			Some(self.parse_expr()?)
+		} else {
+			None
+		};
+
+		self.expect_terminator()?;
+
+		Ok(value)
+	}
+
 	fn parse_if_statement(&mut self) -> Result<IfStatement> {
 		let if_statement = self.next_or_fail()?;
 
 		self.expect_token(&if_statement, TokenType::Keyword, "if")?;
 
 		let if_cond = self.parse_expr()?;
+		let block = self.parse_block()?;
+
+		// `else` has to be on the same line as the closing `}`, as a line return ends the if
+		let peeked = self.peek_token_raw()?;
+
+		if !peeked.is_some_and(|tk| tk.token_type == TokenType::Keyword && tk.content == "else") {
+			return Ok(IfStatement {
+				val: if_cond,
+				block,
+				else_block: None,
+			});
+		}
+
+		self.advance_token()?;
+
+		let next_token = self.peek_or_fail()?;
+
+		let else_block =
+			if TokenType::Keyword == next_token.token_type && next_token.content == "if" {
+				// `else if` is sugar for an else block holding the nested if
+				StatementBlock {
+					statements: Vec::new(),
+					tail: Some(Box::new(LocatedType::new(
+						Expr::If(Box::new(self.parse_if_statement()?)),
+						next_token.location,
+					))),
+				}
+			} else {
+				self.parse_block()?
+			};
 
 		Ok(IfStatement {
 			val: if_cond,
-			block: self.parse_block()?,
+			block,
+			else_block: Some(else_block),
 		})
 	}
 
+	fn parse_match_statement(&mut self) -> Result<MatchStatement> {
+		let match_statement = self.next_or_fail()?;
+
+		self.expect_token(&match_statement, TokenType::Keyword, "match")?;
+
+		let val = self.parse_expr()?;
+
+		let block_start = self.next_or_fail()?;
+
+		self.expect_token(&block_start, TokenType::Operator, "{")?;
+
+		let mut arms = Vec::new();
+
+		loop {
+			let next_token = self.peek_or_fail()?;
+
+			if TokenType::Operator == next_token.token_type && next_token.content == "}" {
+				self.advance_token()?;
+				break;
+			}
+
+			let pattern = self.parse_match_pattern()?;
+
+			let next_token = self.peek_or_fail()?;
+
+			let guard = if TokenType::Keyword == next_token.token_type && next_token.content == "if"
+			{
+				self.advance_token()?;
+				Some(self.parse_expr()?)
+			} else {
+				None
+			};
+
+			let arrow = self.next_or_fail()?;
+			self.expect_token(&arrow, TokenType::Operator, "=>")?;
+
+			arms.push(MatchArm {
+				pattern,
+				guard,
+				block: self.parse_block()?,
+			});
+
+			// Arms may optionally be separated by commas
+			let next_token = self.peek_or_fail()?;
+
+			if TokenType::Operator == next_token.token_type && next_token.content == "," {
+				self.advance_token()?;
+			}
+		}
+
+		Ok(MatchStatement { val, arms })
+	}
+
+	fn parse_match_pattern(&mut self) -> Result<MatchPattern> {
+		let pattern_tk = self.next_or_fail()?;
+
+		match pattern_tk.token_type {
+			TokenType::Identifier if pattern_tk.content == "_" => Ok(MatchPattern::Wildcard),
+			TokenType::Identifier => Ok(MatchPattern::Binding(pattern_tk.content)),
+			TokenType::IntegerLiteral | TokenType::StringLiteral | TokenType::BoolLiteral => {
+				let start = self.parse_literal(pattern_tk)?;
+
+				let next_token = self.peek_or_fail()?;
+
+				let inclusive = match (&next_token.token_type, next_token.content.as_str()) {
+					(TokenType::Operator, "..") => false,
+					(TokenType::Operator, "..=") => true,
+					_ => return Ok(MatchPattern::Literal(start)),
+				};
+
+				self.advance_token()?;
+
+				let end_tk = self.next_or_fail()?;
+
+				Ok(MatchPattern::Range {
+					start,
+					end: self.parse_literal(end_tk)?,
+					inclusive,
+				})
+			}
+			_ => self.unexpected_token(pattern_tk, Some("pattern".to_string())),
+		}
+	}
+
+	fn parse_literal(&self, literal: Token) -> Result<Expr> {
+		Ok(match literal.token_type {
+			TokenType::IntegerLiteral => {
+				Expr::IntLiteral(literal.content.parse().map_err(|e| (literal.clone(), e))?)
+			}
+			TokenType::StringLiteral => Expr::StringLiteral(literal.content),
+			// UNWRAP: BoolLiteral has already been checked
+			TokenType::BoolLiteral => Expr::BoolLiteral(literal.content.parse().unwrap()),
+			_ => return self.unexpected_token(literal, Some("literal".to_string())),
+		})
+	}
+
+	// Assignment has the lowest precedence and is right-associative, so `a = b = 0` assigns both.
 	fn parse_expr(&mut self) -> Result<Expr> {
+		let expr = self.parse_comparison_expr()?;
+
+		let Expr::Identifier(name) = expr else {
+			return Ok(expr);
+		};
+
+		let peeked = self.peek_token_raw()?;
+
+		if !peeked.is_some_and(|tk| tk.token_type == TokenType::Operator && tk.content == "=") {
+			return Ok(Expr::Identifier(name));
+		}
+
+		self.advance_token()?;
+
+		Ok(Expr::Assign(AssignExpr {
+			name,
+			val: Box::new(self.parse_expr()?),
+		}))
+	}
+
+	// Comparisons don't chain: `(a < b) == c` needs parentheses. A line return ends the
+	// expression, so operators are only looked for on the same line.
+	fn parse_comparison_expr(&mut self) -> Result<Expr> {
+		let expr = self.parse_postfix_expr()?;
+
+		let Some(op) = self.peek_token_raw()?.and_then(|tk| comparison_op(&tk)) else {
+			return Ok(expr);
+		};
+
+		self.advance_token()?;
+
+		let expr = Expr::Comparison(ComparisonExpr {
+			op,
+			lhs: Box::new(expr),
+			rhs: Box::new(self.parse_postfix_expr()?),
+		});
+
+		// `a < b < c` would compare a Bool to `c`, which is never what was meant
+		if let Some(token) = self.peek_token_raw()? {
+			if comparison_op(&token).is_some() {
+				return Err(ParserError::ChainedComparison(token.location));
+			}
+		}
+
+		Ok(expr)
+	}
+
+	fn parse_primary_expr(&mut self) -> Result<Expr> {
 		// TODO: extended expressions (calculs, etc...)
 
-		if let TokenType::Identifier = self.peek_or_fail()?.token_type {
-			return self.parse_branch_identifier_expr();
+		let peeked = self.peek_or_fail()?;
+
+		match peeked.token_type {
+			TokenType::Identifier => return self.parse_branch_identifier_expr(),
+			TokenType::Keyword if peeked.content == "if" => {
+				return Ok(Expr::If(Box::new(self.parse_if_statement()?)))
+			}
+			TokenType::Keyword if peeked.content == "loop" => {
+				return Ok(Expr::Loop(self.parse_loop()?))
+			}
+			_ => (),
 		}
 
 		// We consume the token as we are the one doing the parsing
 		let expr_start = self.next_or_fail()?;
 
 		Ok(match expr_start.token_type {
-			TokenType::IntegerLiteral => Expr::IntLiteral(
-				expr_start
-					.content
-					.parse()
-					.map_err(|e| (expr_start.content.clone(), e))?,
-			),
-			TokenType::StringLiteral => Expr::StringLiteral(expr_start.content),
-			// UNWRAP: BoolLiteral has already been checked
-			TokenType::BoolLiteral => Expr::BoolLiteral(expr_start.content.parse().unwrap()),
+			TokenType::IntegerLiteral | TokenType::StringLiteral | TokenType::BoolLiteral => {
+				self.parse_literal(expr_start)?
+			}
 			TokenType::Operator if expr_start.content == "[" => Expr::Array(self.parse_array()?),
+			TokenType::Operator if expr_start.content == "{" => self.parse_map_or_block()?,
+			TokenType::Operator if expr_start.content == "(" => {
+				let expr = self.parse_expr()?;
+
+				let parenth_end = self.next_or_fail()?;
+				self.expect_token(&parenth_end, TokenType::Operator, ")")?;
+
+				expr
+			}
 			_ => return self.unexpected_token(expr_start, Some("expression".to_string())),
 		})
 	}
@@ -178,22 +614,140 @@ impl<T: Read> Parser<T> {
 	fn parse_branch_identifier_expr(&mut self) -> Result<Expr> {
 		let identifier = self.next_or_fail()?;
 
-		let peeked = self.peek_or_fail()?;
+		// A line return ends the expression, so don't look past it
+		let peeked = self.peek_token_raw()?;
+
+		Ok(
+			if peeked
+				.as_ref()
+				.is_some_and(|tk| tk.token_type == TokenType::Operator && tk.content == "(")
+			{
+				self.advance_token()?;
+
+				let args = self.parse_expr_list(")")?;
 
-		// TODO: implement array access
+				Expr::FuncCall(FuncCallExpr {
+					name: identifier.content,
+					args,
+				})
+			} else {
+				Expr::Identifier(identifier.content)
+			},
+		)
+	}
+
+	// Applies the `.field`, `.func(args)` and `[index]` that follow `expr` on the same line, in
+	// any order. `target.func(args)` is a call to `func(target, args)`.
+	fn parse_postfix_expr(&mut self) -> Result<Expr> {
+		let mut expr = self.parse_primary_expr()?;
+
+		while let Some(peeked) = self.peek_token_raw()? {
+			if peeked.token_type != TokenType::Operator {
+				break;
+			}
+
+			match peeked.content.as_str() {
+				"." => {
+					self.advance_token()?;
+
+					let field = self.next_or_fail()?;
+					self.expect_token_type(&field, TokenType::Identifier)?;
+
+					let is_call = self.peek_token_raw()?.is_some_and(|tk| {
+						tk.token_type == TokenType::Operator && tk.content == "("
+					});
+
+					expr = if is_call {
+						self.advance_token()?;
+
+						let mut args = vec![expr];
+						args.extend(self.parse_expr_list(")")?);
+
+						Expr::FuncCall(FuncCallExpr {
+							name: field.content,
+							args,
+						})
+					} else {
+						Expr::Member(MemberExpr {
+							target: Box::new(expr),
+							field: field.content,
+						})
+					};
+				}
+				"[" => {
+					self.advance_token()?;
+
+					let index = self.parse_expr()?;
 
-		if peeked.content == "(" {
+					let index_end = self.next_or_fail()?;
+					self.expect_token(&index_end, TokenType::Operator, "]")?;
+
+					expr = Expr::Index(IndexExpr {
+						target: Box::new(expr),
+						index: Box::new(index),
+					});
+				}
+				_ => break,
+			}
+		}
+
+		Ok(expr)
+	}
+
+	// The `{` has already been consumed. `{}` is an empty map, and a string followed by `:`
+	// starts a map; anything else is a block.
+	fn parse_map_or_block(&mut self) -> Result<Expr> {
+		let next_token = self.peek_or_fail()?;
+
+		if TokenType::Operator == next_token.token_type && next_token.content == "}" {
 			self.advance_token()?;
 
-			let args = self.parse_expr_list(")")?;
+			return Ok(Expr::Map(MapExpr {
+				entries: Vec::new(),
+			}));
+		}
 
-			Ok(Expr::FuncCall(FuncCallExpr {
-				name: identifier.content,
-				args,
-			}))
-		} else {
-			Ok(Expr::Identifier(identifier.content))
+		if TokenType::StringLiteral == next_token.token_type
+			&& self
+				.peek_second_token()?
+				.is_some_and(|tk| tk.token_type == TokenType::Operator && tk.content == ":")
+		{
+			return Ok(Expr::Map(self.parse_map()?));
+		}
+
+		Ok(Expr::Block(self.parse_block_content()?))
+	}
+
+	fn parse_map(&mut self) -> Result<MapExpr> {
+		let mut entries = Vec::new();
+
+		loop {
+			let key_token = self.next_or_fail()?;
+
+			// Also allows a trailing comma
+			if TokenType::Operator == key_token.token_type && key_token.content == "}" {
+				break;
+			}
+
+			self.expect_token_type(&key_token, TokenType::StringLiteral)?;
+
+			let colon = self.next_or_fail()?;
+			self.expect_token(&colon, TokenType::Operator, ":")?;
+
+			entries.push((self.parse_literal(key_token)?, self.parse_expr()?));
+
+			let end_token = self.next_or_fail()?;
+
+			if TokenType::Operator == end_token.token_type && end_token.content == "}" {
+				break;
+			}
+
+			if TokenType::Operator != end_token.token_type || end_token.content != "," {
+				return self.unexpected_token(end_token, Some("one of: , or }".to_string()));
+			}
 		}
+
+		Ok(MapExpr { entries })
 	}
 
 	fn parse_array(&mut self) -> Result<ArrayExpr> {
@@ -202,6 +756,8 @@ impl<T: Read> Parser<T> {
 		Ok(ArrayExpr { args: expr_list })
 	}
 
+	// Parses comma-separated expressions up to and including `end_operator`. The list may be
+	// empty and may end with a trailing comma.
 	fn parse_expr_list(&mut self, end_operator: &str) -> Result<Vec<Expr>> {
 		let mut exprs: Vec<Expr> = Vec::new();
 
@@ -213,6 +769,13 @@ impl<T: Read> Parser<T> {
 				break;
 			}
 
+			// A leading or doubled comma leaves an empty slot in the list
+			if TokenType::Operator == next_token.token_type && next_token.content == "," {
+				let comma = next_token.clone();
+
+				return self.unexpected_token(comma, Some(format!("expression or {end_operator}")));
+			}
+
 			exprs.push(self.parse_expr()?);
 
 			let end_token = self.next_or_fail()?;
@@ -221,14 +784,19 @@ impl<T: Read> Parser<T> {
 				break;
 			}
 
-			self.expect_token(&end_token, TokenType::Operator, ",")?;
+			if TokenType::Operator != end_token.token_type || end_token.content != "," {
+				return self
+					.unexpected_token(end_token, Some(format!("one of: , or {end_operator}")));
+			}
 		}
 
 		Ok(exprs)
 	}
 
-	fn parse_arg_list(&mut self, end_operator: &str) -> Result<Vec<String>> {
-		let mut idents: Vec<String> = Vec::new();
+	// Parses `name` or `name: Type` items separated by commas, up to and including
+	// `end_operator`
+	fn parse_arg_list(&mut self, end_operator: &str) -> Result<Vec<(String, Option<String>)>> {
+		let mut idents: Vec<(String, Option<String>)> = Vec::new();
 
 		loop {
 			let next_token = self.next_or_fail()?;
@@ -239,7 +807,9 @@ impl<T: Read> Parser<T> {
 
 			self.expect_token_type(&next_token, TokenType::Identifier)?;
 
-			idents.push(next_token.content);
+			let type_name = self.parse_type_annotation()?;
+
+			idents.push((next_token.content, type_name));
 
 			let end_token = self.next_or_fail()?;
 
@@ -247,18 +817,46 @@ impl<T: Read> Parser<T> {
 				break;
 			}
 
-			self.expect_token(&end_token, TokenType::Operator, ",")?;
+			if TokenType::Operator != end_token.token_type || end_token.content != "," {
+				return self
+					.unexpected_token(end_token, Some(format!("one of: , or {end_operator}")));
+			}
 		}
 
 		Ok(idents)
 	}
 
+	// Parses an optional `: Type`. Type names are only resolved when the VM declares the
+	// function.
+	fn parse_type_annotation(&mut self) -> Result<Option<String>> {
+		let next_token = self.peek_or_fail()?;
+
+		if next_token.token_type != TokenType::Operator || next_token.content != ":" {
+			return Ok(None);
+		}
+
+		self.advance_token()?;
+
+		let type_token = self.next_or_fail()?;
+
+		self.expect_token_type(&type_token, TokenType::Identifier)?;
+
+		Ok(Some(type_token.content))
+	}
+
 	fn parse_block(&mut self) -> Result<StatementBlock> {
 		let block_start = self.next_or_fail()?;
 
 		self.expect_token(&block_start, TokenType::Operator, "{")?;
 
+		self.parse_block_content()
+	}
+
+	// Parses the statements of a block whose `{` has already been consumed, up to and
+	// including its `}`
+	fn parse_block_content(&mut self) -> Result<StatementBlock> {
 		let mut statements = Vec::new();
+		let mut tail = None;
 
 		loop {
 			let next_token = self.peek_or_fail()?;
@@ -267,16 +865,21 @@ impl<T: Read> Parser<T> {
 				break;
 			}
 
-			let Some(statement) = self.next_package()? else {
-				return Err(ParserError::UnexpectedEndOfFile);
-			};
-
-			statements.push(statement);
+			match self.parse_block_item(true)? {
+				// Stray `;` carry nothing, so `{ ;; }` is as empty as `{}`
+				Some(BlockItem::Statement(LocatedType {
+					inner: ParsedHighLevel::Noop,
+					..
+				})) => (),
+				Some(BlockItem::Statement(statement)) => statements.push(statement),
+				Some(BlockItem::Tail(expr)) => tail = Some(Box::new(expr)),
+				None => return Err(self.unexpected_eof()),
+			}
 		}
 
 		self.advance_token()?;
 
-		Ok(StatementBlock { statements })
+		Ok(StatementBlock { statements, tail })
 	}
 
 	#[track_caller]
@@ -287,7 +890,7 @@ impl<T: Read> Parser<T> {
 		// );
 
 		if tk.token_type != tk_type {
-			return self.unexpected_token(tk.clone(), Some(format!("{tk_type:?}")));
+			return self.unexpected_token(tk.clone(), Some(tk_type.to_string()));
 		}
 
 		Ok(())
@@ -297,8 +900,11 @@ impl<T: Read> Parser<T> {
 	fn expect_token(&self, tk: &Token, tk_type: TokenType, content: &str) -> Result<()> {
 		// println!("expect_token called: {}", std::panic::Location::caller());
 
-		self.expect_token_type(tk, tk_type)
-			.and(self.expect_token_content(tk, content))
+		if tk.token_type != tk_type {
+			return self.unexpected_token(tk.clone(), Some(format!("\"{content}\"")));
+		}
+
+		self.expect_token_content(tk, content)
 	}
 
 	#[track_caller]
@@ -309,7 +915,7 @@ impl<T: Read> Parser<T> {
 		// );
 
 		if tk.content != content {
-			return self.unexpected_token(tk.clone(), Some(content.to_string()));
+			return self.unexpected_token(tk.clone(), Some(format!("\"{content}\"")));
 		}
 
 		Ok(())
@@ -325,9 +931,18 @@ impl<T: Read> Parser<T> {
 		Err(ParserError::UnexpectedToken(tk, expected))
 	}
 
+	fn unexpected_eof(&self) -> ParserError {
+		let location = self
+			.last_location
+			.clone()
+			.unwrap_or_else(|| Location::new_z(0, 0, self.source.clone()));
+
+		ParserError::UnexpectedEndOfFile(location)
+	}
+
 	fn peek_or_fail(&mut self) -> Result<Token> {
 		let Some(token) = self.peek_token()? else {
-			return Err(ParserError::UnexpectedEndOfFile);
+			return Err(self.unexpected_eof());
 		};
 
 		Ok(token)
@@ -337,13 +952,48 @@ impl<T: Read> Parser<T> {
 		self.advance_token()?;
 
 		let Some(token) = self.current_token.clone() else {
-			return Err(ParserError::UnexpectedEndOfFile);
+			return Err(self.unexpected_eof());
 		};
 
 		Ok(token)
 	}
 
+	// Expects the end of a statement: a `;`, a line return, EOF, or the `}` closing the block
+	// (left for the block to consume; a stray one is still reported at the top level)
+	fn expect_terminator(&mut self) -> Result<()> {
+		let Some(token) = self.peek_token_raw()? else {
+			return Ok(());
+		};
+
+		match token.token_type {
+			TokenType::LineReturn => {
+				self.lookahead_token = None;
+				Ok(())
+			}
+			TokenType::Operator if token.content == ";" => self.advance_token(),
+			TokenType::Operator if token.content == "}" => Ok(()),
+			_ => self.unexpected_token(token, Some("\";\", \"}\" or a new line".to_string())),
+		}
+	}
+
+	// Peeks the next token, skipping line returns
 	fn peek_token(&mut self) -> Result<Option<Token>> {
+		loop {
+			let token = self.peek_token_raw()?;
+
+			if token
+				.as_ref()
+				.is_some_and(|tk| tk.token_type == TokenType::LineReturn)
+			{
+				self.lookahead_token = None;
+				continue;
+			}
+
+			return Ok(token);
+		}
+	}
+
+	fn peek_token_raw(&mut self) -> Result<Option<Token>> {
 		if let Some(token) = self.lookahead_token.clone() {
 			return Ok(Some(token));
 		}
@@ -353,26 +1003,47 @@ impl<T: Read> Parser<T> {
 		Ok(self.lookahead_token.clone())
 	}
 
-	fn retain_token(&mut self) {
-		self.retain_last_token = true;
+	// Peeks the token following the lookahead one, skipping line returns
+	fn peek_second_token(&mut self) -> Result<Option<Token>> {
+		if self.peek_token()?.is_none() {
+			return Ok(None);
+		}
+
+		loop {
+			let pending = self
+				.pending_tokens
+				.iter()
+				.find(|tk| tk.token_type != TokenType::LineReturn);
+
+			if let Some(token) = pending {
+				return Ok(Some(token.clone()));
+			}
+
+			match self.lexer.next_token() {
+				Ok(token) => self.pending_tokens.push_back(token),
+				Err(lexer::Error::EndOfFile) => return Ok(None),
+				Err(err) => return Err(err.into()),
+			}
+		}
 	}
 
 	fn advance_token(&mut self) -> Result<()> {
-		if self.retain_last_token {
-			self.retain_last_token = false;
-			return Ok(());
-		}
+		self.peek_token()?;
 
-		if let Some(token) = self.lookahead_token.take() {
-			self.current_token = Some(token)
-		} else {
-			self.current_token = self.read_token()?
+		self.current_token = self.lookahead_token.take();
+
+		if let Some(token) = self.current_token.as_ref() {
+			self.last_location = Some(token.location.clone());
 		}
 
 		Ok(())
 	}
 
 	fn read_token(&mut self) -> Result<Option<Token>> {
+		if let Some(token) = self.pending_tokens.pop_front() {
+			return Ok(Some(token));
+		}
+
 		let result = self.lexer.next_token();
 
 		if let Err(lexer::Error::EndOfFile) = result {
@@ -382,3 +1053,333 @@ impl<T: Read> Parser<T> {
 		}
 	}
 }
+
+fn comparison_op(tk: &Token) -> Option<ComparisonOp> {
+	if tk.token_type != TokenType::Operator {
+		return None;
+	}
+
+	Some(match tk.content.as_str() {
+		"==" => ComparisonOp::Equal,
+		"!=" => ComparisonOp::NotEqual,
+		"<" => ComparisonOp::Less,
+		"<=" => ComparisonOp::LessEqual,
+		">" => ComparisonOp::Greater,
+		">=" => ComparisonOp::GreaterEqual,
+		_ => return None,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::{error::ParserError, types::*, Parser};
+	use crate::lexer::Lexer;
+
+	fn parser(source: &str) -> Parser<Cursor<String>> {
+		Parser::new(
+			Lexer::new(Cursor::new(source.to_string()), "test".into()),
+			"test".into(),
+		)
+	}
+
+	fn parse(source: &str) -> Vec<ParsedHighLevel> {
+		parser(source)
+			.parse_all()
+			.unwrap_or_else(|err| panic!("{source:?} should parse: {err}"))
+			.into_iter()
+			.map(|package| package.inner)
+			.collect()
+	}
+
+	// The value of `let v = <source>;`
+	fn parse_value(source: &str) -> Expr {
+		match &parse(&format!("let v = {source};"))[..] {
+			[ParsedHighLevel::VarDecl(VarAssign { val, .. })] => val.clone(),
+			packages => panic!("expected a single declaration, got {packages:?}"),
+		}
+	}
+
+	fn parse_errors(source: &str) -> Vec<String> {
+		let (_, errors) = parser(source).parse_all_with_recovery();
+
+		errors.iter().map(ParserError::to_string).collect()
+	}
+
+	fn parse_err(source: &str) -> String {
+		match parser(source).parse_all() {
+			Ok(packages) => panic!("expected {source:?} to fail, got {packages:?}"),
+			Err(err) => err.to_string(),
+		}
+	}
+
+	#[test]
+	fn reports_independent_errors() {
+		let errors = parse_errors("let = 1;\nprintln(1);\nlet y 2;\nprintln(2);\n");
+
+		assert_eq!(errors.len(), 2, "{errors:?}");
+		assert!(errors[0].starts_with("test:1:5:"), "{errors:?}");
+		assert!(errors[1].starts_with("test:3:7:"), "{errors:?}");
+
+		let (packages, _) = parser("let = 1;\nprintln(1);\n").parse_all_with_recovery();
+		assert!(matches!(
+			packages[..],
+			[LocatedType {
+				inner: ParsedHighLevel::FuncCall(_),
+				..
+			}]
+		));
+	}
+
+	#[test]
+	fn renders_expected_tokens() {
+		assert_eq!(
+			parse_err("let x 2;"),
+			r#"test:1:7: unexpected token "2", expected "=""#
+		);
+		assert_eq!(
+			parse_err("println(1 2);"),
+			r#"test:1:11: unexpected token "2", expected one of: , or )"#
+		);
+		assert_eq!(
+			parse_err("fn 3() {}"),
+			r#"test:1:4: unexpected token "3", expected identifier"#
+		);
+	}
+
+	#[test]
+	fn every_error_is_located() {
+		assert_eq!(
+			parse_err("let x = ;"),
+			r#"test:1:9: unexpected token ";", expected expression"#
+		);
+		assert_eq!(parse_err("println(1"), "test:1:9: Unexpected End of File");
+		assert_eq!(parse_err("let x = $;"), "test:1:9: Invalid character: '$'");
+		assert_eq!(parse_err(r#"let x = "abc"#), "test:1:9: Unexpected EOF");
+	}
+
+	#[test]
+	fn integer_literal_overflow() {
+		assert_eq!(
+			parse_err("let x = 9223372036854775808;"),
+			r#"test:1:9: integer literal too large for i64: "9223372036854775808""#
+		);
+		assert_eq!(
+			parse_err("let x = -9223372036854775809;"),
+			r#"test:1:9: integer literal too large for i64: "-9223372036854775809""#
+		);
+
+		// Only decimal literals are lexed: a hex one must not be misread as a decimal number
+		assert_eq!(
+			parse_err("let x = 0xFFFFFFFFFFFFFFFFF;"),
+			"test:1:10: Invalid character: 'x'"
+		);
+		assert_eq!(
+			parse_err("let x = 12a;"),
+			"test:1:11: Invalid character: 'a'"
+		);
+	}
+
+	#[test]
+	fn identifier_before_block_is_an_if_condition() {
+		let packages = parse(r#"if flag { println("hi"); }"#);
+
+		let [ParsedHighLevel::If(IfStatement {
+			val: Expr::Identifier(condition),
+			block,
+			else_block: None,
+		})] = &packages[..]
+		else {
+			panic!("expected a single if, got {packages:?}");
+		};
+
+		assert_eq!(condition, "flag");
+		assert!(matches!(
+			block.statements[..],
+			[LocatedType {
+				inner: ParsedHighLevel::FuncCall(_),
+				..
+			}]
+		));
+	}
+
+	#[test]
+	fn trailing_commas() {
+		let sources = [
+			"f(1, 2,);",
+			"f();",
+			"let a = [1, 2,];",
+			"let a = [];",
+			r#"let m = { "a": 1, };"#,
+			"let m = {};",
+			"fn f(a, b,) {}",
+			"fn f() {}",
+			"fn f(a: Integer,): Integer { a }",
+			"match x { 1 => {}, _ => {}, }",
+			"match x { 1 => {} }",
+		];
+
+		for source in sources {
+			parse(source);
+		}
+
+		let [ParsedHighLevel::FuncCall(call)] = &parse("f(1, 2,);")[..] else {
+			panic!("expected a call");
+		};
+		assert_eq!(call.args.len(), 2);
+	}
+
+	#[test]
+	fn empty_list_items() {
+		assert_eq!(
+			parse_err("f(,);"),
+			r#"test:1:3: unexpected token ",", expected expression or )"#
+		);
+		assert_eq!(
+			parse_err("f(1,,2);"),
+			r#"test:1:5: unexpected token ",", expected expression or )"#
+		);
+		assert_eq!(
+			parse_err("let a = [1,,];"),
+			r#"test:1:12: unexpected token ",", expected expression or ]"#
+		);
+
+		let [ParsedHighLevel::FuncCall(call)] = &parse("f(1,);")[..] else {
+			panic!("expected a call");
+		};
+		assert_eq!(call.args.len(), 1);
+	}
+
+	#[test]
+	fn optional_semicolon_before_brace() {
+		for source in [
+			"fn f() { println(1); }",
+			"fn f() { println(1) }",
+			"fn f() { let x = 1 }",
+			"if true { x = 1 } else { x = 2; }",
+		] {
+			let [ParsedHighLevel::FuncDecl(FuncDecl { block, .. })
+			| ParsedHighLevel::If(IfStatement { block, .. })] = &parse(source)[..]
+			else {
+				panic!("{source:?} should be a single statement");
+			};
+
+			// Without the semicolon, a call before `}` is the block's tail
+			assert_eq!(
+				block.statements.len() + usize::from(block.tail.is_some()),
+				1,
+				"{source:?}"
+			);
+		}
+
+		assert!(parse_err("fn f() { println(1) println(2) }").contains("unexpected token"));
+	}
+
+	#[test]
+	fn stray_semicolons_and_empty_blocks() {
+		assert!(parse("").is_empty());
+		assert!(parse(";;;")
+			.iter()
+			.all(|package| matches!(package, ParsedHighLevel::Noop)));
+
+		let [ParsedHighLevel::Block(block)] = &parse("{}")[..] else {
+			panic!("expected a single block");
+		};
+		assert!(block.statements.is_empty() && block.tail.is_none());
+
+		let [ParsedHighLevel::Block(block)] = &parse("{ ;; }")[..] else {
+			panic!("expected a single block");
+		};
+		assert!(block.statements.is_empty() && block.tail.is_none());
+
+		let [ParsedHighLevel::Block(block)] = &parse("{ println(\"x\"); }")[..] else {
+			panic!("expected a single block");
+		};
+		assert_eq!(block.statements.len(), 1);
+
+		let [ParsedHighLevel::VarDecl(VarAssign {
+			val: Expr::Map(map),
+			..
+		})] = &parse("let v = {};")[..]
+		else {
+			panic!("expected a map literal");
+		};
+		assert!(map.entries.is_empty());
+	}
+
+	#[test]
+	fn member_access() {
+		let Expr::Member(MemberExpr { target, field }) = parse_value("a.b.c") else {
+			panic!("expected a member access");
+		};
+		assert_eq!(field, "c");
+
+		let Expr::Member(MemberExpr { target, field }) = *target else {
+			panic!("expected a nested member access");
+		};
+		assert_eq!(field, "b");
+		assert!(matches!(*target, Expr::Identifier(name) if name == "a"));
+
+		assert!(parse_err("let v = a.;").contains("unexpected token"));
+	}
+
+	#[test]
+	fn chained_postfix() {
+		// `a.b[0].c(1).d` is `c(a.b[0], 1).d`
+		let Expr::Member(MemberExpr { target, field }) = parse_value("a.b[0].c(1).d") else {
+			panic!("expected a member access");
+		};
+		assert_eq!(field, "d");
+
+		let Expr::FuncCall(FuncCallExpr { name, args }) = *target else {
+			panic!("expected a method call");
+		};
+		assert_eq!(name, "c");
+		assert!(matches!(args[..], [Expr::Index(_), Expr::IntLiteral(1)]));
+
+		let Expr::Index(IndexExpr { target, .. }) = &args[0] else {
+			unreachable!();
+		};
+		assert!(matches!(**target, Expr::Member(MemberExpr { ref field, .. }) if field == "b"));
+
+		assert!(matches!(parse_value("f(1)[0][1]"), Expr::Index(_)));
+		assert!(matches!(parse_value("[[1]][0].g()"), Expr::FuncCall(_)));
+	}
+
+	#[test]
+	fn chained_comparisons() {
+		assert_eq!(
+			parse_err("let v = 1 < 2 < 3;"),
+			"test:1:15: comparison operators cannot be chained, compare each pair separately"
+		);
+		assert!(parse_err("let v = a == b != c;").contains("cannot be chained"));
+		assert!(matches!(
+			parse_value("(1 < 2) == true"),
+			Expr::Comparison(_)
+		));
+	}
+
+	#[test]
+	fn type_annotations() {
+		let [ParsedHighLevel::FuncDecl(func_decl)] =
+			&parse("fn f(a: Integer, b, c: Vary): Bool { true }")[..]
+		else {
+			panic!("expected a function declaration");
+		};
+		assert_eq!(func_decl.args, ["a", "b", "c"]);
+		assert_eq!(
+			func_decl.arg_types,
+			[Some("Integer".to_string()), None, Some("Vary".to_string())]
+		);
+		assert_eq!(func_decl.ret_type.as_deref(), Some("Bool"));
+
+		let [ParsedHighLevel::FuncDecl(func_decl)] = &parse("fn g() { 1 }")[..] else {
+			panic!("expected a function declaration");
+		};
+		assert_eq!(func_decl.ret_type, None);
+
+		assert!(parse_err("fn f(a:) { 1 }").contains("unexpected token"));
+		assert!(parse_err("fn f(a): { 1 }").contains("unexpected token"));
+	}
+}
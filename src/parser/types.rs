@@ -24,6 +24,32 @@ impl<T: Clone + Debug> AsRef<T> for LocatedType<T> {
 pub struct IfStatement {
 	pub val: Expr,
 	pub block: StatementBlock,
+	pub else_block: Option<StatementBlock>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MatchPattern {
+	Wildcard,
+	Binding(String),
+	Literal(Expr),
+	Range {
+		start: Expr,
+		end: Expr,
+		inclusive: bool,
+	},
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+	pub pattern: MatchPattern,
+	pub guard: Option<Expr>,
+	pub block: StatementBlock,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchStatement {
+	pub val: Expr,
+	pub arms: Vec<MatchArm>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,23 +63,77 @@ pub struct ArrayExpr {
 	pub args: Vec<Expr>,
 }
 
+/// String-keyed map literal: `{ "a": 1, "b": 2 }`
+#[derive(Debug, Clone)]
+pub struct MapExpr {
+	pub entries: Vec<(Expr, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexExpr {
+	pub target: Box<Expr>,
+	pub index: Box<Expr>,
+}
+
+/// Map field access: `a.b` reads the same entry as `a["b"]`
+#[derive(Debug, Clone)]
+pub struct MemberExpr {
+	pub target: Box<Expr>,
+	pub field: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonOp {
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparisonExpr {
+	pub op: ComparisonOp,
+	pub lhs: Box<Expr>,
+	pub rhs: Box<Expr>,
+}
+
+/// Assignment in expression position, evaluating to the assigned value
+#[derive(Debug, Clone)]
+pub struct AssignExpr {
+	pub name: String,
+	pub val: Box<Expr>,
+}
+
 #[derive(Debug, Clone)]
 pub struct VarAssign {
 	pub name: String,
 	pub val: Expr,
 }
 
+/// `alias name = target`, making `name(...)` call `target`
+#[derive(Debug, Clone)]
+pub struct AliasDecl {
+	pub name: String,
+	pub target: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct FuncDecl {
 	pub name: String,
 	pub args: Vec<String>,
-	// pub ret_type: VmType,
+	/// Type annotation of each argument, `None` when it has none
+	pub arg_types: Vec<Option<String>>,
+	pub ret_type: Option<String>,
 	pub block: StatementBlock,
 }
 
 #[derive(Debug, Clone)]
 pub struct StatementBlock {
 	pub statements: Vec<LocatedType<ParsedHighLevel>>,
+	/// Expression written last without a terminator, which gives the block its value
+	pub tail: Option<Box<LocatedType<Expr>>>,
 	// pub ret_type: VmType,
 }
 
@@ -65,14 +145,29 @@ pub enum Expr {
 	Identifier(String),
 	FuncCall(FuncCallExpr),
 	Array(ArrayExpr),
+	Map(MapExpr),
+	Index(IndexExpr),
+	Member(MemberExpr),
+	Comparison(ComparisonExpr),
+	Assign(AssignExpr),
+	Block(StatementBlock),
+	If(Box<IfStatement>),
+	/// Repeats its block until a `break`, whose value it takes
+	Loop(StatementBlock),
 }
 
 #[derive(Debug, Clone)]
 pub enum ParsedHighLevel {
 	Noop,
 	VarDecl(VarAssign),
+	ConstDecl(VarAssign),
 	VarSet(VarAssign),
 	FuncDecl(FuncDecl),
 	FuncCall(FuncCallExpr),
 	If(IfStatement),
+	Match(MatchStatement),
+	Loop(StatementBlock),
+	Block(StatementBlock),
+	Break(Option<Expr>),
+	Alias(AliasDecl),
 }
@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns tracing on or off for the whole process.
+pub fn set_enabled(enabled: bool) {
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Logs to stderr when tracing is enabled. The arguments aren't evaluated otherwise.
+#[macro_export]
+macro_rules! trace {
+	($($arg:tt)*) => {
+		if $crate::trace::enabled() {
+			eprintln!("[trace] {}", format_args!($($arg)*));
+		}
+	};
+}
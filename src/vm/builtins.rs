@@ -1,12 +1,19 @@
-use std::process::Command;
-
-use crate::common::Location;
+use std::{
+	cmp::Ordering,
+	collections::BTreeMap,
+	ffi::OsString,
+	io::{BufRead, BufReader, ErrorKind, Read, Write},
+	num::IntErrorKind,
+	process::{Child, ChildStdout, Command, ExitStatus, Stdio},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use super::{
 	error::{VmError, VmResult, VmResultExt},
-	types::VmTypable,
-	variant::VmVariant,
-	Builtin, IntoVariant, Vm,
+	json,
+	types::{VmTypable, VmType},
+	variant::{TryFromVariant, VmVariant},
+	Builtin, IntoVariant, Scope, Vm,
 };
 
 impl Vm {
@@ -17,103 +24,456 @@ impl Vm {
 	pub fn register_default_builtins(&mut self) {
 		self.register_builtin("println".to_string(), Self::builtin_println);
 		self.register_builtin("exec".to_string(), Self::builtin_exec);
+		self.register_builtin("exec_capture".to_string(), Self::builtin_exec_capture);
+		self.register_builtin("spawn".to_string(), Self::builtin_spawn);
+		self.register_builtin("wait".to_string(), Self::builtin_wait);
+		self.register_builtin("kill".to_string(), Self::builtin_kill);
+		self.register_builtin("pipe".to_string(), Self::builtin_pipe);
+		self.register_builtin("exec_with_input".to_string(), Self::builtin_exec_with_input);
 		self.register_builtin("env".to_string(), Self::builtin_env);
+		self.register_builtin("env_all".to_string(), Self::builtin_env_all);
+		self.register_builtin("cwd".to_string(), Self::builtin_cwd);
+		self.register_builtin("chdir".to_string(), Self::builtin_chdir);
+		self.register_builtin("list_dir".to_string(), Self::builtin_list_dir);
+		self.register_builtin("exists".to_string(), Self::builtin_path_check);
+		self.register_builtin("is_file".to_string(), Self::builtin_path_check);
+		self.register_builtin("is_dir".to_string(), Self::builtin_path_check);
+		self.register_builtin("file_size".to_string(), Self::builtin_file_size);
+		self.register_builtin("read_all".to_string(), Self::builtin_read_all);
+		self.register_builtin("read_lines".to_string(), Self::builtin_read_lines);
+		self.register_builtin("sleep".to_string(), Self::builtin_sleep);
+		self.register_builtin("now".to_string(), Self::builtin_now);
+		self.register_builtin("random".to_string(), Self::builtin_random);
+		self.register_builtin("seed".to_string(), Self::builtin_seed);
 		self.register_builtin("typename".to_string(), Self::builtin_typename);
+		self.register_builtin("is_unit".to_string(), Self::builtin_is_type);
+		self.register_builtin("is_bool".to_string(), Self::builtin_is_type);
+		self.register_builtin("is_int".to_string(), Self::builtin_is_type);
+		self.register_builtin("is_string".to_string(), Self::builtin_is_type);
+		self.register_builtin("is_array".to_string(), Self::builtin_is_type);
+		self.register_builtin("is_map".to_string(), Self::builtin_is_type);
+		self.register_builtin("is_bytes".to_string(), Self::builtin_is_type);
+		self.register_builtin("expect_type".to_string(), Self::builtin_expect_type);
+		self.register_builtin("slice".to_string(), Self::builtin_slice);
+		self.register_builtin("sort".to_string(), Self::builtin_sort);
+		self.register_builtin("sort_by".to_string(), Self::builtin_sort_by);
+		self.register_builtin("reverse".to_string(), Self::builtin_reverse);
+		self.register_builtin("map".to_string(), Self::builtin_map);
+		self.register_builtin("filter".to_string(), Self::builtin_filter);
+		self.register_builtin("time_call".to_string(), Self::builtin_time_call);
+		self.register_builtin("apply".to_string(), Self::builtin_apply);
+		self.register_builtin("display_array".to_string(), Self::builtin_display_array);
+		self.register_builtin("to_kv".to_string(), Self::builtin_to_kv);
+		self.register_builtin("min".to_string(), Self::builtin_min_max);
+		self.register_builtin("max".to_string(), Self::builtin_min_max);
+		self.register_builtin("clamp".to_string(), Self::builtin_clamp);
+		self.register_builtin("sum".to_string(), Self::builtin_sum);
+		self.register_builtin("to_json".to_string(), Self::builtin_to_json);
+		self.register_builtin("from_json".to_string(), Self::builtin_from_json);
+		self.register_builtin("to_int".to_string(), Self::builtin_to_int);
+		self.register_builtin("abs".to_string(), Self::builtin_abs);
+		self.register_builtin("pow".to_string(), Self::builtin_pow);
+		self.register_builtin("isqrt".to_string(), Self::builtin_isqrt);
+		self.register_builtin("keys".to_string(), Self::builtin_keys);
+		self.register_builtin("values".to_string(), Self::builtin_values);
+		self.register_builtin("entries".to_string(), Self::builtin_entries);
+		self.register_builtin("from_entries".to_string(), Self::builtin_from_entries);
+		self.register_builtin("get_field".to_string(), Self::builtin_get_field);
+		self.register_builtin("set_field".to_string(), Self::builtin_set_field);
+		self.register_builtin("insert".to_string(), Self::builtin_insert);
+		self.register_builtin("remove".to_string(), Self::builtin_remove);
+		self.register_builtin("count".to_string(), Self::builtin_count);
+		self.register_builtin("frequencies".to_string(), Self::builtin_frequencies);
+		self.register_builtin("zip".to_string(), Self::builtin_zip);
+		self.register_builtin("enumerate".to_string(), Self::builtin_enumerate);
+		self.register_builtin("collect".to_string(), Self::builtin_collect);
+		self.register_builtin("flatten".to_string(), Self::builtin_flatten);
+		self.register_builtin("flat_map".to_string(), Self::builtin_flat_map);
+		self.register_builtin("unique".to_string(), Self::builtin_unique);
+		self.register_builtin("repeat".to_string(), Self::builtin_repeat);
+		self.register_builtin("starts_with".to_string(), Self::builtin_starts_ends_with);
+		self.register_builtin("ends_with".to_string(), Self::builtin_starts_ends_with);
+		self.register_builtin("lines".to_string(), Self::builtin_lines);
+		self.register_builtin("trim".to_string(), Self::builtin_trim);
+		self.register_builtin("trim_start".to_string(), Self::builtin_trim);
+		self.register_builtin("trim_end".to_string(), Self::builtin_trim);
+		self.register_builtin("trim_matches".to_string(), Self::builtin_trim_matches);
+		self.register_builtin("ord".to_string(), Self::builtin_ord);
+		self.register_builtin("chr".to_string(), Self::builtin_chr);
+		self.register_builtin("bytes".to_string(), Self::builtin_bytes);
+		self.register_builtin("pad_left".to_string(), Self::builtin_pad);
+		self.register_builtin("pad_right".to_string(), Self::builtin_pad);
+		self.register_builtin("format_num".to_string(), Self::builtin_format_num);
+		self.register_builtin("here".to_string(), Self::builtin_here);
+		self.register_builtin("dump_scope".to_string(), Self::builtin_dump_scope);
+		self.register_builtin("args".to_string(), Self::builtin_args);
+		self.register_builtin("exit".to_string(), Self::builtin_exit);
+		self.register_builtin("assert_eq".to_string(), Self::builtin_assert_eq_ne);
+		self.register_builtin("assert_ne".to_string(), Self::builtin_assert_eq_ne);
+		self.register_builtin("assert_true".to_string(), Self::builtin_assert_true);
+		self.register_builtin("assert_contains".to_string(), Self::builtin_assert_contains);
 	}
 
-	pub fn builtin_println(&mut self, _name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
-		if !args.is_empty() {
-			print!("{}", args[0]);
+	fn check_arg_count(
+		&self,
+		func_name: &str,
+		args: &[VmVariant],
+		expected: usize,
+	) -> VmResult<()> {
+		if args.len() != expected {
+			return Err(VmError::wrong_arg_count(expected, args.len()))
+				.with_context_func_call(self.caller_location(), func_name.to_string());
 		}
 
-		for elem in args.iter().skip(1) {
-			print!(" {}", elem);
-		}
+		Ok(())
+	}
+
+	fn native_arg<T: TryFromVariant>(
+		&self,
+		func_name: &str,
+		arg_name: &str,
+		arg: VmVariant,
+	) -> VmResult<T> {
+		arg.try_native().with_context_func_arg(
+			self.caller_location(),
+			func_name.to_string(),
+			arg_name.to_string(),
+		)
+	}
+
+	pub fn builtin_println(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		let elems: Vec<String> = args.iter().map(VmVariant::to_string).collect();
 
-		println!();
+		if let Err(err) = writeln!(self.output, "{}", elems.join(" ")) {
+			return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name);
+		}
 
 		Ok(VmVariant::Unit)
 	}
 
 	pub fn builtin_exec(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
-		if args.is_empty() {
-			return Err(
-				VmError::wrong_arg_count(1, 0).with_context_func_call(self.caller_location(), name)
+		let (mut cmd_builder, _) = self.build_command(&name, &mut args)?;
+
+		let mut process = match cmd_builder.spawn() {
+			Ok(v) => v,
+			Err(err) => {
+				self.report_error(format_args!("Could not spawn process: {err}"));
+				return Ok((-1).into_variant());
+			}
+		};
+
+		let status = process.wait().expect("Command wasn't running");
+
+		Ok(exit_code(status).into_variant())
+	}
+
+	// Like `exec`, returning `{ "status": ..., "stdout": ..., "stderr": ... }` instead of just
+	// the status. With the "timed" option, `duration_ms` is added with how long it ran.
+	pub fn builtin_exec_capture(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		let (mut cmd_builder, options) = self.build_command(&name, &mut args)?;
+
+		let start = Instant::now();
+
+		let output = match cmd_builder.output() {
+			Ok(output) => output,
+			Err(err) => {
+				return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name)
+			}
+		};
+
+		let millis = start.elapsed().as_millis();
+		let mut result = BTreeMap::new();
+
+		result.insert(
+			"status".to_string(),
+			exit_code(output.status).into_variant(),
+		);
+		result.insert("stdout".to_string(), captured_output(output.stdout));
+		result.insert("stderr".to_string(), captured_output(output.stderr));
+
+		if options.iter().any(|opt| opt == "timed") {
+			result.insert(
+				"duration_ms".to_string(),
+				VmVariant::Integer(i64::try_from(millis).unwrap_or(i64::MAX)),
 			);
 		}
 
+		Ok(result.into_variant())
+	}
+
+	// Like `exec`, without waiting for the process to end. Returns its PID, to give to `wait`
+	// or `kill`.
+	pub fn builtin_spawn(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		let (mut cmd_builder, _) = self.build_command(&name, &mut args)?;
+
+		let process = match cmd_builder.spawn() {
+			Ok(v) => v,
+			Err(err) => {
+				self.report_error(format_args!("Could not spawn process: {err}"));
+				return Ok((-1).into_variant());
+			}
+		};
+
+		let pid = process.id();
+
+		self.children.insert(pid, process);
+
+		Ok(i64::from(pid).into_variant())
+	}
+
+	// Waits for a process started by `spawn` and returns its exit code, like `exec` does.
+	pub fn builtin_wait(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let mut process = self.take_child(&name, args.remove(0))?;
+
+		match process.wait() {
+			Ok(status) => Ok(exit_code(status).into_variant()),
+			Err(err) => Err(VmError::io(err)).with_context_func_call(self.caller_location(), name),
+		}
+	}
+
+	// Kills a process started by `spawn`, and waits for it so it doesn't linger as a zombie.
+	pub fn builtin_kill(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let mut process = self.take_child(&name, args.remove(0))?;
+
+		if let Err(err) = process.kill().and_then(|_| process.wait()) {
+			return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name);
+		}
+
+		Ok(VmVariant::Unit)
+	}
+
+	// Takes `[options], command, args...` off `args`, as given to `exec` and `spawn`, and
+	// returns the options along with the command
+	fn build_command(
+		&self,
+		name: &str,
+		args: &mut Vec<VmVariant>,
+	) -> VmResult<(Command, Vec<String>)> {
+		if args.is_empty() {
+			return Err(VmError::wrong_arg_count(1, 0)
+				.with_context_func_call(self.caller_location(), name.to_string()));
+		}
+
 		let mut options: Vec<String> = Vec::new();
 
-		for elem in args.remove(0).try_native::<Vec<VmVariant>>()? {
+		let opts: Vec<VmVariant> = args.remove(0).try_native().with_context_func_arg(
+			self.caller_location(),
+			name.to_string(),
+			"exec_opt".to_string(),
+		)?;
+
+		for elem in opts {
 			let VmVariant::String(opt) = elem else {
 				return Err(VmError::invalid_value_type(
 					"String[]".to_string(),
 					"Vary[]".to_string(),
 				)
-				.with_context_func_arg(self.caller_location(), name, "exec_opt".to_string()));
+				.with_context_func_arg(
+					self.caller_location(),
+					name.to_string(),
+					"exec_opt".to_string(),
+				));
 			};
 
 			options.push(opt);
 		}
 
 		if args.is_empty() {
-			return Err(
-				VmError::wrong_arg_count(2, 1).with_context_func_call(self.caller_location(), name)
-			);
+			return Err(VmError::wrong_arg_count(2, 1)
+				.with_context_func_call(self.caller_location(), name.to_string()));
+		}
+
+		trace!("executing command {:?} with options {options:?}", args[0]);
+
+		Ok((self.command_from_args(name, args)?, options))
+	}
+
+	// Takes `command, args...` off `args`. Arguments are Strings, or Bytes for raw ones.
+	fn command_from_args(&self, name: &str, args: &mut Vec<VmVariant>) -> VmResult<Command> {
+		if args.is_empty() {
+			return Err(VmError::wrong_arg_count(1, 0)
+				.with_context_func_call(self.caller_location(), name.to_string()));
 		}
 
 		let command: String = args.remove(0).try_native().with_context_func_arg(
 			self.caller_location(),
-			name.clone(),
+			name.to_string(),
 			"command".to_string(),
 		)?;
 
-		// println!("[VM DEBUG] executing command {command:?} with options {options:?}");
-
 		let mut cmd_builder = Command::new(command);
 
 		for (idx, arg) in args.drain(..).enumerate() {
-			let cmd_arg: String = arg.try_native().with_context_func_arg(
+			let cmd_arg = match arg {
+				VmVariant::Bytes(bytes) => os_string_from_bytes(bytes),
+				arg => arg.try_native::<String>().map(OsString::from),
+			};
+
+			cmd_builder.arg(cmd_arg.with_context_func_arg(
 				self.caller_location(),
-				name.clone(),
+				name.to_string(),
 				format!("command_arg{idx}"),
+			)?);
+		}
+
+		Ok(cmd_builder)
+	}
+
+	// Runs each `[command, args...]` with its stdout feeding the next one's stdin, and returns
+	// what the last one wrote. A stage that can't start or exits unsuccessfully fails the whole
+	// pipe, naming that stage. Like in a shell, a stage killed by SIGPIPE because a later one
+	// stopped reading (`yes | head -n1`) hasn't failed.
+	pub fn builtin_pipe(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		if args.is_empty() {
+			return Err(
+				VmError::wrong_arg_count(1, 0).with_context_func_call(self.caller_location(), name)
+			);
+		}
+
+		let mut commands = Vec::with_capacity(args.len());
+
+		for arg in args {
+			let mut stage: Vec<VmVariant> = self.native_arg(&name, "command", arg)?;
+
+			commands.push(self.command_from_args(&name, &mut stage)?);
+		}
+
+		let stage_names: Vec<String> = commands
+			.iter()
+			.enumerate()
+			.map(|(idx, command)| {
+				format!("stage{idx} ({})", command.get_program().to_string_lossy())
+			})
+			.collect();
+		let last_stage = commands.len() - 1;
+
+		let mut processes: Vec<Child> = Vec::with_capacity(commands.len());
+		let mut previous_stdout: Option<ChildStdout> = None;
+
+		for (idx, mut command) in commands.into_iter().enumerate() {
+			if let Some(stdout) = previous_stdout.take() {
+				command.stdin(Stdio::from(stdout));
+			}
+
+			let mut process = match command.stdout(Stdio::piped()).spawn() {
+				Ok(process) => process,
+				Err(err) => {
+					for mut started in processes {
+						let _ = started.kill();
+						let _ = started.wait();
+					}
+
+					return Err(VmError::io(err)).with_context_func_arg(
+						self.caller_location(),
+						name,
+						stage_names[idx].clone(),
+					);
+				}
+			};
+
+			previous_stdout = process.stdout.take();
+			processes.push(process);
+		}
+
+		let mut output = Vec::new();
+
+		// UNWRAP: every stage has its stdout piped, and only the last one's is left
+		let read_res = previous_stdout.unwrap().read_to_end(&mut output);
+
+		for (idx, mut process) in processes.into_iter().enumerate() {
+			let res = match process.wait() {
+				Ok(status) if status.success() => Ok(()),
+				Ok(status) if idx != last_stage && killed_by_sigpipe(status) => Ok(()),
+				Ok(status) => Err(VmError::command_failed(status)),
+				Err(err) => Err(VmError::io(err)),
+			};
+
+			res.with_context_func_arg(
+				self.caller_location(),
+				name.clone(),
+				stage_names[idx].clone(),
 			)?;
+		}
 
-			cmd_builder.arg(cmd_arg);
+		if let Err(err) = read_res {
+			return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name);
 		}
 
-		let mut process = match cmd_builder.spawn() {
-			Ok(v) => v,
+		Ok(captured_output(output))
+	}
+
+	// Runs `command, args...` with `input` (a String or Bytes) as its stdin, and returns what it
+	// wrote to stdout. Like with `pipe`, exiting unsuccessfully is an error.
+	pub fn builtin_exec_with_input(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		if args.len() < 2 {
+			return Err(VmError::wrong_arg_count(2, args.len()))
+				.with_context_func_call(self.caller_location(), name);
+		}
+
+		let input = match args.remove(0) {
+			VmVariant::Bytes(bytes) => bytes,
+			arg => self.native_arg::<String>(&name, "input", arg)?.into_bytes(),
+		};
+
+		let mut command = self.command_from_args(&name, &mut args)?;
+
+		command.stdin(Stdio::piped()).stdout(Stdio::piped());
+
+		let mut process = match command.spawn() {
+			Ok(process) => process,
 			Err(err) => {
-				eprintln!("Could not spawn process: {err}");
-				return Ok((-1).into_variant());
+				return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name)
 			}
 		};
 
-		let exit;
+		// UNWRAP: stdin was piped just above
+		let mut stdin = process.stdin.take().unwrap();
 
-		#[cfg(target_family = "unix")]
-		{
-			use std::os::unix::process::ExitStatusExt;
+		// Written from another thread, as the child may fill its stdout before reading all of
+		// it. A child exiting without reading everything only closes the pipe early.
+		let writer = std::thread::spawn(move || match stdin.write_all(&input) {
+			Err(err) if err.kind() != ErrorKind::BrokenPipe => Err(err),
+			_ => Ok(()),
+		});
 
-			exit = process.wait().expect("Command wasn't running").into_raw();
-		}
-		#[cfg(not(target_family = "unix"))]
-		{
-			let status = process.wait().expect("Command wasn't running");
+		let output = process.wait_with_output();
+		let write_res = writer.join().expect("stdin writer panicked");
 
-			if let Some(code) = status.code() {
-				exit = code;
-			} else {
-				if status.success() {
-					exit = 0;
-				} else {
-					exit = 1;
-				}
+		let output = match output.and_then(|output| write_res.map(|_| output)) {
+			Ok(output) => output,
+			Err(err) => {
+				return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name)
 			}
+		};
+
+		if !output.status.success() {
+			return Err(VmError::command_failed(output.status))
+				.with_context_func_call(self.caller_location(), name);
 		}
 
-		Ok(exit.into_variant())
+		Ok(captured_output(output.stdout))
+	}
+
+	fn take_child(&mut self, name: &str, arg: VmVariant) -> VmResult<Child> {
+		let pid: i64 = self.native_arg(name, "pid", arg)?;
+
+		u32::try_from(pid)
+			.ok()
+			.and_then(|pid| self.children.remove(&pid))
+			.ok_or_else(|| VmError::invalid_value(format!("no spawned process with pid {pid}")))
+			.with_context_func_arg(self.caller_location(), name.to_string(), "pid".to_string())
 	}
 
 	pub fn builtin_env(
@@ -123,7 +483,7 @@ impl Vm {
 	) -> VmResult<VmVariant> {
 		if args.len() != 1 {
 			return Err(VmError::wrong_arg_count(1, args.len()))
-				.with_context_func_call(Location::new_z(0, 0, String::new()), func_name);
+				.with_context_func_call(self.caller_location(), func_name);
 		}
 
 		let arg = args.remove(0);
@@ -140,6 +500,232 @@ impl Vm {
 		}
 	}
 
+	// Variables whose name or value isn't valid Unicode are left out.
+	pub fn builtin_env_all(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 0)?;
+
+		let vars: BTreeMap<String, VmVariant> = std::env::vars_os()
+			.filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+			.map(|(key, value)| (key, value.into_variant()))
+			.collect();
+
+		Ok(vars.into_variant())
+	}
+
+	pub fn builtin_cwd(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 0)?;
+
+		match std::env::current_dir() {
+			Ok(dir) => Ok(dir.to_string_lossy().into_owned().into_variant()),
+			Err(err) => Err(VmError::io(err)).with_context_func_call(self.caller_location(), name),
+		}
+	}
+
+	// Changes the working directory of the whole process, so it applies to later `exec` calls.
+	pub fn builtin_chdir(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let path: String = self.native_arg(&name, "path", args.remove(0))?;
+
+		if let Err(err) = std::env::set_current_dir(path) {
+			return Err(VmError::io(err)).with_context_func_arg(
+				self.caller_location(),
+				name,
+				"path".to_string(),
+			);
+		}
+
+		Ok(VmVariant::Unit)
+	}
+
+	// Entry names (not full paths), sorted so scripts behave the same on every filesystem.
+	pub fn builtin_list_dir(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let path: String = self.native_arg(&name, "path", args.remove(0))?;
+
+		let entries = std::fs::read_dir(path).and_then(|entries| {
+			entries
+				.map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+				.collect::<std::io::Result<Vec<String>>>()
+		});
+
+		let mut entries = match entries {
+			Ok(entries) => entries,
+			Err(err) => {
+				return Err(VmError::io(err)).with_context_func_arg(
+					self.caller_location(),
+					name,
+					"path".to_string(),
+				);
+			}
+		};
+
+		entries.sort_unstable();
+
+		Ok(entries.into_variant())
+	}
+
+	// Shared by `exists`, `is_file` and `is_dir`, which are all false for a missing path.
+	pub fn builtin_path_check(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let path: String = self.native_arg(&name, "path", args.remove(0))?;
+
+		let Ok(metadata) = std::fs::metadata(path) else {
+			return Ok(false.into_variant());
+		};
+
+		let res = match name.as_str() {
+			"exists" => true,
+			"is_file" => metadata.is_file(),
+			"is_dir" => metadata.is_dir(),
+			_ => return Err(VmError::unknown_identifier(name).with_context_internal()),
+		};
+
+		Ok(res.into_variant())
+	}
+
+	pub fn builtin_file_size(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let path: String = self.native_arg(&name, "path", args.remove(0))?;
+
+		let size = match std::fs::metadata(path) {
+			Ok(metadata) => metadata.len(),
+			Err(err) => {
+				return Err(VmError::io(err)).with_context_func_arg(
+					self.caller_location(),
+					name,
+					"path".to_string(),
+				);
+			}
+		};
+
+		match i64::try_from(size) {
+			Ok(size) => Ok(size.into_variant()),
+			Err(_) => Err(VmError::integer_overflow())
+				.with_context_func_call(self.caller_location(), name),
+		}
+	}
+
+	// Reads the rest of the VM's input stream, which isn't available when the script itself is
+	// read from stdin.
+	pub fn builtin_read_all(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 0)?;
+
+		let Some(input) = self.input.as_mut() else {
+			return Err(VmError::no_input()).with_context_func_call(self.caller_location(), name);
+		};
+
+		let mut content = String::new();
+
+		if let Err(err) = input.read_to_string(&mut content) {
+			return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name);
+		}
+
+		Ok(content.into_variant())
+	}
+
+	// Reads the file line by line rather than as a whole, with the same splitting as `lines`.
+	pub fn builtin_read_lines(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let path: String = self.native_arg(&name, "path", args.remove(0))?;
+
+		let lines = std::fs::File::open(path).and_then(|file| {
+			BufReader::new(file)
+				.lines()
+				.collect::<std::io::Result<Vec<String>>>()
+		});
+
+		match lines {
+			Ok(lines) => Ok(lines.into_variant()),
+			Err(err) => Err(VmError::io(err)).with_context_func_arg(
+				self.caller_location(),
+				name,
+				"path".to_string(),
+			),
+		}
+	}
+
+	pub fn builtin_sleep(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let millis: i64 = self.native_arg(&name, "ms", args.remove(0))?;
+
+		let Ok(millis) = u64::try_from(millis) else {
+			return Err(VmError::invalid_value(format!(
+				"negative duration: {millis}"
+			)))
+			.with_context_func_arg(self.caller_location(), name, "ms".to_string());
+		};
+
+		std::thread::sleep(Duration::from_millis(millis));
+
+		Ok(VmVariant::Unit)
+	}
+
+	// Milliseconds since the Unix epoch
+	pub fn builtin_now(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 0)?;
+
+		let millis = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |elapsed| elapsed.as_millis());
+
+		Ok(VmVariant::Integer(millis as i64))
+	}
+
+	// Returns an integer in [min, max)
+	pub fn builtin_random(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let min: i64 = self.native_arg(&name, "min", args.remove(0))?;
+		let max: i64 = self.native_arg(&name, "max", args.remove(0))?;
+
+		if min >= max {
+			return Err(VmError::invalid_value(format!("empty range: {min}..{max}")))
+				.with_context_func_call(self.caller_location(), name);
+		}
+
+		let width = (max as i128 - min as i128) as u128;
+		let offset = xorshift64(&mut self.rng_state) as u128 % width;
+
+		Ok(VmVariant::Integer((min as i128 + offset as i128) as i64))
+	}
+
+	// Makes the following `random` calls reproducible
+	pub fn builtin_seed(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let seed: i64 = self.native_arg(&name, "seed", args.remove(0))?;
+
+		self.rng_state = non_zero_seed(seed as u64);
+
+		Ok(VmVariant::Unit)
+	}
+
 	pub fn builtin_typename(
 		&mut self,
 		func_name: String,
@@ -147,9 +733,2311 @@ impl Vm {
 	) -> VmResult<VmVariant> {
 		if args.len() != 1 {
 			return Err(VmError::wrong_arg_count(1, args.len()))
-				.with_context_func_call(Location::new_z(0, 0, String::new()), func_name);
+				.with_context_func_call(self.caller_location(), func_name);
 		}
 
 		Ok(args.remove(0).get_typeinfo().to_string().into_variant())
 	}
+
+	// Shared by the `is_*` predicates, dispatching on the called name.
+	pub fn builtin_is_type(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let expected = match name.as_str() {
+			"is_unit" => VmType::Unit,
+			"is_bool" => VmType::Bool,
+			"is_int" => VmType::Integer,
+			"is_string" => VmType::String,
+			"is_array" => VmType::Array,
+			"is_map" => VmType::Map,
+			"is_bytes" => VmType::Bytes,
+			_ => return Err(VmError::unknown_identifier(name).with_context_internal()),
+		};
+
+		Ok((args.remove(0).get_typeinfo() == expected).into_variant())
+	}
+
+	// Returns `value` when `typename(value)` is `type` (or `type` is `Vary`), so it can guard
+	// function arguments.
+	pub fn builtin_expect_type(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let value = args.remove(0);
+		let type_name: String = self.native_arg(&name, "type", args.remove(0))?;
+
+		// A typo in the type name would otherwise look like a mismatch
+		let Some(expected) = VmType::from_name(&type_name) else {
+			return Err(VmError::unknown_type(type_name)).with_context_func_arg(
+				self.caller_location(),
+				name,
+				"type".to_string(),
+			);
+		};
+
+		let value_type = value.get_typeinfo();
+
+		if !expected.accepts(&value_type) {
+			return Err(VmError::invalid_value_type(
+				type_name,
+				value_type.to_string(),
+			))
+			.with_context_func_arg(self.caller_location(), name, "value".to_string());
+		}
+
+		Ok(value)
+	}
+
+	// Elements are cloned into the new array (nested arrays included), so mutating the
+	// slice never affects the original. Only `Ref` elements keep sharing their target.
+	pub fn builtin_slice(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 3)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+		let start: i64 = self.native_arg(&name, "start", args.remove(0))?;
+		let end: i64 = self.native_arg(&name, "end", args.remove(0))?;
+
+		let len = array.len();
+
+		let start = native_index(start, len)
+			.and_then(|start| {
+				if start > len {
+					Err(VmError::index_out_of_bounds(start as i64, len))
+				} else {
+					Ok(start)
+				}
+			})
+			.with_context_func_arg(self.caller_location(), name.clone(), "start".to_string())?;
+
+		let end = native_index(end, len)
+			.with_context_func_arg(self.caller_location(), name, "end".to_string())?
+			.min(len);
+
+		if end <= start {
+			return Ok(VmVariant::Array(Vec::new()));
+		}
+
+		Ok(array[start..end].into_variant())
+	}
+
+	pub fn builtin_sort(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+
+		let sorted = try_sort_by(array, &mut compare_variants).with_context_func_arg(
+			self.caller_location(),
+			name,
+			"array".to_string(),
+		)?;
+
+		Ok(sorted.into_variant())
+	}
+
+	// The comparison function must return a negative, zero or positive Integer.
+	pub fn builtin_sort_by(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+		let cmp_func: String = self.native_arg(&name, "cmp_func", args.remove(0))?;
+
+		let sorted = try_sort_by(array, &mut |lhs, rhs| {
+			let res: i64 = self
+				.call_func(cmp_func.clone(), vec![lhs.clone(), rhs.clone()])?
+				.try_native()
+				.with_context_func_arg(
+					self.caller_location(),
+					name.clone(),
+					"cmp_func".to_string(),
+				)?;
+
+			Ok(res.cmp(&0))
+		})?;
+
+		Ok(sorted.into_variant())
+	}
+
+	pub fn builtin_map(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+		let func: String = self.native_arg(&name, "func", args.remove(0))?;
+
+		let mapped = array
+			.into_iter()
+			.map(|elem| self.call_func(func.clone(), vec![elem]))
+			.collect::<VmResult<Vec<VmVariant>>>()?;
+
+		Ok(mapped.into_variant())
+	}
+
+	// Calls `func` with the remaining arguments and returns `{ "result": ..., "ms": ... }`.
+	pub fn builtin_time_call(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		if args.is_empty() {
+			return Err(
+				VmError::wrong_arg_count(1, 0).with_context_func_call(self.caller_location(), name)
+			);
+		}
+
+		let func: String = self.native_arg(&name, "func", args.remove(0))?;
+
+		let start = Instant::now();
+		let result = self.call_func(func, args)?;
+		let millis = start.elapsed().as_millis();
+
+		let mut timing = BTreeMap::new();
+
+		timing.insert("result".to_string(), result);
+		timing.insert(
+			"ms".to_string(),
+			VmVariant::Integer(i64::try_from(millis).unwrap_or(i64::MAX)),
+		);
+
+		Ok(timing.into_variant())
+	}
+
+	// Calls `func` with the elements of `args` as its arguments.
+	pub fn builtin_apply(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let func: String = self.native_arg(&name, "func", args.remove(0))?;
+		let func_args: Vec<VmVariant> = self.native_arg(&name, "args", args.remove(0))?;
+
+		self.call_func(func, func_args)
+	}
+
+	// The predicate must return a Bool.
+	pub fn builtin_filter(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+		let predicate: String = self.native_arg(&name, "predicate", args.remove(0))?;
+
+		let mut filtered = Vec::new();
+
+		for elem in array {
+			let keep: bool = self
+				.call_func(predicate.clone(), vec![elem.clone()])?
+				.try_native()
+				.with_context_func_arg(
+					self.caller_location(),
+					name.clone(),
+					"predicate".to_string(),
+				)?;
+
+			if keep {
+				filtered.push(elem);
+			}
+		}
+
+		Ok(filtered.into_variant())
+	}
+
+	pub fn builtin_reverse(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let mut array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+
+		array.reverse();
+
+		Ok(array.into_variant())
+	}
+
+	// Each element is formatted like `println` would, e.g. `display_array([1, 2], "", "|", "")`
+	// gives `1|2`.
+	pub fn builtin_display_array(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 4)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+		let prefix: String = self.native_arg(&name, "prefix", args.remove(0))?;
+		let separator: String = self.native_arg(&name, "separator", args.remove(0))?;
+		let suffix: String = self.native_arg(&name, "suffix", args.remove(0))?;
+
+		let elems: Vec<String> = array.iter().map(VmVariant::to_string).collect();
+
+		Ok(format!("{prefix}{}{suffix}", elems.join(&separator)).into_variant())
+	}
+
+	// `key=value` for each entry of a Map, in key order.
+	pub fn builtin_to_kv(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+		let separator: String = self.native_arg(&name, "separator", args.remove(0))?;
+
+		let entries: Vec<String> = map
+			.iter()
+			.map(|(key, value)| format!("{key}={value}"))
+			.collect();
+
+		Ok(entries.join(&separator).into_variant())
+	}
+
+	fn native_integer_array(&self, func_name: &str, arg: VmVariant) -> VmResult<Vec<i64>> {
+		let array: Vec<VmVariant> = self.native_arg(func_name, "array", arg)?;
+
+		array
+			.into_iter()
+			.map(|elem| self.native_arg(func_name, "array", elem))
+			.collect()
+	}
+
+	// Shared by `min` and `max`, dispatching on the called name. Accepts either a single
+	// Integer array, or two comparable scalars.
+	pub fn builtin_min_max(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		if args.len() == 2 {
+			let rhs = args.pop().unwrap();
+			let lhs = args.pop().unwrap();
+
+			let ord = compare_variants(&lhs, &rhs)
+				.with_context_func_call(self.caller_location(), name.clone())?;
+
+			let take_lhs = if name == "min" {
+				ord != Ordering::Greater
+			} else {
+				ord != Ordering::Less
+			};
+
+			return Ok(if take_lhs { lhs } else { rhs });
+		}
+
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array = self.native_integer_array(&name, args.remove(0))?;
+
+		let res = if name == "min" {
+			array.into_iter().min()
+		} else {
+			array.into_iter().max()
+		};
+
+		let Some(res) = res else {
+			return Err(VmError::empty_array()).with_context_func_arg(
+				self.caller_location(),
+				name,
+				"array".to_string(),
+			);
+		};
+
+		Ok(res.into_variant())
+	}
+
+	pub fn builtin_clamp(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 3)?;
+
+		let hi = args.pop().unwrap();
+		let lo = args.pop().unwrap();
+		let value = args.pop().unwrap();
+
+		if compare_variants(&lo, &hi)
+			.with_context_func_call(self.caller_location(), name.clone())?
+			== Ordering::Greater
+		{
+			return Err(VmError::invalid_value(format!(
+				"lower bound {lo} is greater than upper bound {hi}"
+			)))
+			.with_context_func_call(self.caller_location(), name);
+		}
+
+		let res = if compare_variants(&value, &lo).with_context_func_arg(
+			self.caller_location(),
+			name.clone(),
+			"lo".to_string(),
+		)? == Ordering::Less
+		{
+			lo
+		} else if compare_variants(&value, &hi).with_context_func_arg(
+			self.caller_location(),
+			name,
+			"hi".to_string(),
+		)? == Ordering::Greater
+		{
+			hi
+		} else {
+			value
+		};
+
+		Ok(res)
+	}
+
+	pub fn builtin_sum(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array = self.native_integer_array(&name, args.remove(0))?;
+
+		let mut sum: i64 = 0;
+
+		for elem in array {
+			let Some(res) = sum.checked_add(elem) else {
+				return Err(VmError::integer_overflow())
+					.with_context_func_call(self.caller_location(), name);
+			};
+
+			sum = res;
+		}
+
+		Ok(sum.into_variant())
+	}
+
+	// `to_int(string)` understands `0x`, `0o` and `0b` prefixes and defaults to base 10, while
+	// `to_int(string, radix)` only takes digits (and a sign) in that radix.
+	pub fn builtin_to_int(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		if args.is_empty() || args.len() > 2 {
+			return Err(VmError::wrong_arg_count(args.len().clamp(1, 2), args.len()))
+				.with_context_func_call(self.caller_location(), name);
+		}
+
+		let string: String = self.native_arg(&name, "string", args.remove(0))?;
+
+		let (digits, radix) = if args.is_empty() {
+			split_radix_prefix(&string)
+		} else {
+			let radix: i64 = self.native_arg(&name, "radix", args.remove(0))?;
+
+			match u32::try_from(radix) {
+				Ok(radix) if (2..=36).contains(&radix) => (string.clone(), radix),
+				_ => {
+					return Err(VmError::invalid_value(format!(
+						"radix must be between 2 and 36, got {radix}"
+					)))
+					.with_context_func_arg(
+						self.caller_location(),
+						name,
+						"radix".to_string(),
+					);
+				}
+			}
+		};
+
+		let res = i64::from_str_radix(&digits, radix).map_err(|err| match err.kind() {
+			IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => VmError::integer_overflow(),
+			_ => VmError::invalid_integer(string, radix),
+		});
+
+		res.map(IntoVariant::into_variant).with_context_func_arg(
+			self.caller_location(),
+			name,
+			"string".to_string(),
+		)
+	}
+
+	pub fn builtin_abs(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let value: i64 = self.native_arg(&name, "value", args.remove(0))?;
+
+		let Some(res) = value.checked_abs() else {
+			return Err(VmError::integer_overflow())
+				.with_context_func_call(self.caller_location(), name);
+		};
+
+		Ok(res.into_variant())
+	}
+
+	// Integers only: a negative exponent is an error rather than a truncated 0.
+	pub fn builtin_pow(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let base: i64 = self.native_arg(&name, "base", args.remove(0))?;
+		let exp: i64 = self.native_arg(&name, "exp", args.remove(0))?;
+
+		if exp < 0 {
+			return Err(VmError::invalid_value(format!("negative exponent {exp}")))
+				.with_context_func_arg(self.caller_location(), name, "exp".to_string());
+		}
+
+		let res = match (base, u32::try_from(exp)) {
+			(_, Ok(exp)) => base.checked_pow(exp),
+			(0 | 1, Err(_)) => Some(base),
+			(-1, Err(_)) => Some(if exp % 2 == 0 { 1 } else { -1 }),
+			(_, Err(_)) => None,
+		};
+
+		let Some(res) = res else {
+			return Err(VmError::integer_overflow())
+				.with_context_func_call(self.caller_location(), name);
+		};
+
+		Ok(res.into_variant())
+	}
+
+	pub fn builtin_isqrt(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let value: i64 = self.native_arg(&name, "value", args.remove(0))?;
+
+		if value < 0 {
+			return Err(VmError::invalid_value(format!(
+				"square root of negative number {value}"
+			)))
+			.with_context_func_arg(self.caller_location(), name, "value".to_string());
+		}
+
+		Ok(value.isqrt().into_variant())
+	}
+
+	pub fn builtin_to_json(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		Ok(json::to_json(&args.remove(0)).into_variant())
+	}
+
+	pub fn builtin_from_json(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let source: String = self.native_arg(&name, "json", args.remove(0))?;
+
+		json::from_json(&source).with_context_func_arg(
+			self.caller_location(),
+			name,
+			"json".to_string(),
+		)
+	}
+
+	// Keys come out sorted, like every iteration over a map.
+	pub fn builtin_keys(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+
+		Ok(map.into_keys().collect::<Vec<String>>().into_variant())
+	}
+
+	pub fn builtin_values(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+
+		Ok(map.into_values().collect::<Vec<VmVariant>>().into_variant())
+	}
+
+	// `[key, value]` pairs in key order, the reverse of `from_entries`.
+	pub fn builtin_entries(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+
+		Ok(map
+			.into_iter()
+			.map(|(key, value)| [key.into_variant(), value].into_variant())
+			.collect::<Vec<VmVariant>>()
+			.into_variant())
+	}
+
+	// Builds a Map from `[key, value]` pairs. Keys must be Strings, and appear only once.
+	pub fn builtin_from_entries(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let pairs: Vec<VmVariant> = self.native_arg(&name, "pairs", args.remove(0))?;
+		let mut map = BTreeMap::new();
+
+		for pair in pairs {
+			let mut pair: Vec<VmVariant> = self.native_arg(&name, "pairs", pair)?;
+
+			if pair.len() != 2 {
+				return Err(VmError::invalid_value(format!(
+					"expected a [key, value] pair, got {} elements",
+					pair.len()
+				)))
+				.with_context_func_arg(self.caller_location(), name, "pairs".to_string());
+			}
+
+			let value = pair.remove(1);
+			let key: String = self.native_arg(&name, "pairs", pair.remove(0))?;
+
+			if map.contains_key(&key) {
+				return Err(VmError::invalid_value(format!("duplicate key: {key}")))
+					.with_context_func_arg(self.caller_location(), name, "pairs".to_string());
+			}
+
+			map.insert(key, value);
+		}
+
+		Ok(map.into_variant())
+	}
+
+	// Like `map.field`, with a field name computed at runtime.
+	pub fn builtin_get_field(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let mut map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+		let field: String = self.native_arg(&name, "field", args.remove(0))?;
+
+		map.remove(&field)
+			.ok_or_else(|| VmError::unknown_key(field))
+			.with_context_func_arg(self.caller_location(), name, "field".to_string())
+	}
+
+	// Values are passed by copy, so the Map to update is given by the name of the variable
+	// holding it (like functions are for `map`). Only existing fields can be set.
+	pub fn builtin_set_field(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 3)?;
+
+		let var_name: String = self.native_arg(&name, "var", args.remove(0))?;
+		let field: String = self.native_arg(&name, "field", args.remove(0))?;
+
+		let mut map: BTreeMap<String, VmVariant> = self
+			.get_variable(&var_name)
+			.and_then(VmVariant::try_native)
+			.with_context_func_arg(self.caller_location(), name.clone(), "var".to_string())?;
+
+		let Some(slot) = map.get_mut(&field) else {
+			return Err(VmError::unknown_key(field)).with_context_func_arg(
+				self.caller_location(),
+				name,
+				"field".to_string(),
+			);
+		};
+
+		*slot = args.remove(0);
+
+		self.set_variable(var_name, map).with_context_func_arg(
+			self.caller_location(),
+			name,
+			"var".to_string(),
+		)?;
+
+		Ok(VmVariant::Unit)
+	}
+
+	// Returns a new map, the original one is left untouched.
+	pub fn builtin_insert(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 3)?;
+
+		let mut map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+		let key: String = self.native_arg(&name, "key", args.remove(0))?;
+
+		map.insert(key, args.remove(0));
+
+		Ok(map.into_variant())
+	}
+
+	// Returns a new map without `key`. Removing a missing key isn't an error.
+	pub fn builtin_remove(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let mut map: BTreeMap<String, VmVariant> = self.native_arg(&name, "map", args.remove(0))?;
+		let key: String = self.native_arg(&name, "key", args.remove(0))?;
+
+		map.remove(&key);
+
+		Ok(map.into_variant())
+	}
+
+	// Elements that can't be compared with `value` are skipped rather than being an error.
+	pub fn builtin_count(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+		let value = args.remove(0);
+
+		let count = array
+			.iter()
+			.filter(|elem| elem.compare(&value) == Some(Ordering::Equal))
+			.count();
+
+		Ok(VmVariant::Integer(count as i64))
+	}
+
+	// Map keys being strings, elements are counted by their displayed form: `1` and `"1"` are
+	// counted together.
+	pub fn builtin_frequencies(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+
+		let mut frequencies: BTreeMap<String, i64> = BTreeMap::new();
+
+		for elem in array {
+			*frequencies.entry(elem.to_string()).or_default() += 1;
+		}
+
+		let frequencies: BTreeMap<String, VmVariant> = frequencies
+			.into_iter()
+			.map(|(key, count)| (key, VmVariant::Integer(count)))
+			.collect();
+
+		Ok(frequencies.into_variant())
+	}
+
+	// Pairs up elements, stopping at the end of the shorter array.
+	pub fn builtin_zip(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let lhs: Vec<VmVariant> = self.native_arg(&name, "lhs", args.remove(0))?;
+		let rhs: Vec<VmVariant> = self.native_arg(&name, "rhs", args.remove(0))?;
+
+		let pairs: Vec<VmVariant> = lhs
+			.into_iter()
+			.zip(rhs)
+			.map(|(lhs, rhs)| [lhs, rhs].into_variant())
+			.collect();
+
+		Ok(pairs.into_variant())
+	}
+
+	// Returns `[index, element]` pairs.
+	pub fn builtin_enumerate(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+
+		let pairs: Vec<VmVariant> = array
+			.into_iter()
+			.enumerate()
+			.map(|(idx, elem)| [VmVariant::Integer(idx as i64), elem].into_variant())
+			.collect();
+
+		Ok(pairs.into_variant())
+	}
+
+	// Turns a value into an array: a String gives its characters as one-character strings, a Map
+	// its `[key, value]` pairs in key order, and an Array is returned as is.
+	pub fn builtin_collect(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		match args.remove(0) {
+			VmVariant::Array(array) => Ok(array.into_variant()),
+			VmVariant::String(string) => Ok(string
+				.chars()
+				.map(|c| c.to_string().into_variant())
+				.collect::<Vec<VmVariant>>()
+				.into_variant()),
+			VmVariant::Map(map) => Ok(map
+				.into_iter()
+				.map(|(key, value)| [key.into_variant(), value].into_variant())
+				.collect::<Vec<VmVariant>>()
+				.into_variant()),
+			value => Err(VmError::invalid_value_type(
+				"Array, Map or String".to_string(),
+				value.get_typeinfo().to_string(),
+			))
+			.with_context_func_arg(self.caller_location(), name, "value".to_string()),
+		}
+	}
+
+	// Flattens one level deep. Elements that aren't arrays are kept as they are.
+	pub fn builtin_flatten(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+
+		Ok(flatten(array).into_variant())
+	}
+
+	// Same as `flatten(map(array, func))`.
+	pub fn builtin_flat_map(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		let mapped: Vec<VmVariant> = self.builtin_map(name, args)?.try_native()?;
+
+		Ok(flatten(mapped).into_variant())
+	}
+
+	// Keeps the first occurrence of each element. `VmVariant` isn't hashable, so this compares
+	// every pair; elements that can't be compared are considered distinct.
+	pub fn builtin_unique(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let array: Vec<VmVariant> = self.native_arg(&name, "array", args.remove(0))?;
+
+		let mut unique: Vec<VmVariant> = Vec::with_capacity(array.len());
+
+		for elem in array {
+			if !unique
+				.iter()
+				.any(|seen| seen.compare(&elem) == Some(Ordering::Equal))
+			{
+				unique.push(elem);
+			}
+		}
+
+		Ok(unique.into_variant())
+	}
+
+	// Repeats a String or an Array `count` times.
+	pub fn builtin_repeat(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let value = args.remove(0);
+		let count: i64 = self.native_arg(&name, "count", args.remove(0))?;
+
+		let Ok(count) = usize::try_from(count) else {
+			return Err(VmError::invalid_value(format!("negative count: {count}")))
+				.with_context_func_arg(self.caller_location(), name, "count".to_string());
+		};
+
+		// Reserving up front turns a result too large to allocate into an error instead of a panic
+		// or an abort
+		let too_large = || VmError::invalid_value(format!("result too large: {count} repetitions"));
+
+		match value {
+			VmVariant::String(string) => {
+				let mut res = String::new();
+
+				if string
+					.len()
+					.checked_mul(count)
+					.is_none_or(|len| res.try_reserve_exact(len).is_err())
+				{
+					return Err(too_large()).with_context_func_arg(
+						self.caller_location(),
+						name,
+						"count".to_string(),
+					);
+				}
+
+				// Also keeps an empty string from looping `count` times
+				if !string.is_empty() {
+					for _ in 0..count {
+						res.push_str(&string);
+					}
+				}
+
+				Ok(res.into_variant())
+			}
+			VmVariant::Array(array) => {
+				let mut res = Vec::new();
+
+				if array
+					.len()
+					.checked_mul(count)
+					.is_none_or(|len| res.try_reserve_exact(len).is_err())
+				{
+					return Err(too_large()).with_context_func_arg(
+						self.caller_location(),
+						name,
+						"count".to_string(),
+					);
+				}
+
+				if !array.is_empty() {
+					for _ in 0..count {
+						res.extend_from_slice(&array);
+					}
+				}
+
+				Ok(res.into_variant())
+			}
+			value => Err(VmError::invalid_value_type(
+				"String or Array".to_string(),
+				value.get_typeinfo().to_string(),
+			))
+			.with_context_func_arg(self.caller_location(), name, "value".to_string()),
+		}
+	}
+
+	// Splits on `\n` (dropping a `\r` before it) like Rust's `str::lines`, so a final line
+	// return doesn't add an empty line.
+	pub fn builtin_lines(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let string: String = self.native_arg(&name, "string", args.remove(0))?;
+
+		Ok(string.lines().collect::<Vec<&str>>().into_variant())
+	}
+
+	// Code point of the character of a one-character string.
+	pub fn builtin_ord(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let string: String = self.native_arg(&name, "char", args.remove(0))?;
+
+		let c = single_char(&string).with_context_func_arg(
+			self.caller_location(),
+			name,
+			"char".to_string(),
+		)?;
+
+		Ok((c as u32).into_variant())
+	}
+
+	// Surrogates and values past U+10FFFF aren't characters, and are rejected.
+	pub fn builtin_chr(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let code: i64 = self.native_arg(&name, "code", args.remove(0))?;
+
+		let Some(c) = u32::try_from(code).ok().and_then(char::from_u32) else {
+			return Err(VmError::invalid_value(format!(
+				"not a valid code point: {code}"
+			)))
+			.with_context_func_arg(self.caller_location(), name, "code".to_string());
+		};
+
+		Ok(c.to_string().into_variant())
+	}
+
+	// Builds Bytes from an array of Integers, each between 0 and 255.
+	pub fn builtin_bytes(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let values = self.native_integer_array(&name, args.remove(0))?;
+		let mut bytes = Vec::with_capacity(values.len());
+
+		for value in values {
+			let Ok(byte) = u8::try_from(value) else {
+				return Err(VmError::invalid_value(format!("not a byte: {value}")))
+					.with_context_func_arg(self.caller_location(), name, "array".to_string());
+			};
+
+			bytes.push(byte);
+		}
+
+		Ok(VmVariant::Bytes(bytes))
+	}
+
+	// Shared by `trim`, `trim_start` and `trim_end`, which strip whitespace.
+	pub fn builtin_trim(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let string: String = self.native_arg(&name, "string", args.remove(0))?;
+
+		let trimmed = match name.as_str() {
+			"trim" => string.trim(),
+			"trim_start" => string.trim_start(),
+			"trim_end" => string.trim_end(),
+			_ => return Err(VmError::unknown_identifier(name).with_context_internal()),
+		};
+
+		Ok(trimmed.into_variant())
+	}
+
+	// Strips any of the characters in `chars` from both ends; their order doesn't matter.
+	pub fn builtin_trim_matches(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let string: String = self.native_arg(&name, "string", args.remove(0))?;
+		let chars: String = self.native_arg(&name, "chars", args.remove(0))?;
+
+		Ok(string.trim_matches(|c| chars.contains(c)).into_variant())
+	}
+
+	// Shared by `starts_with` and `ends_with`, dispatching on the called name.
+	pub fn builtin_starts_ends_with(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let string: String = self.native_arg(&name, "string", args.remove(0))?;
+		let pattern: String = self.native_arg(&name, "pattern", args.remove(0))?;
+
+		let res = if name == "starts_with" {
+			string.starts_with(&pattern)
+		} else {
+			string.ends_with(&pattern)
+		};
+
+		Ok(res.into_variant())
+	}
+
+	// Shared by `pad_left` and `pad_right`. Widths count characters, not bytes, and a string
+	// that is already wide enough is returned unchanged.
+	pub fn builtin_pad(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 3)?;
+
+		let string: String = self.native_arg(&name, "string", args.remove(0))?;
+		let width: i64 = self.native_arg(&name, "width", args.remove(0))?;
+		let fill: String = self.native_arg(&name, "fill", args.remove(0))?;
+
+		let fill_char = single_char(&fill).with_context_func_arg(
+			self.caller_location(),
+			name.clone(),
+			"fill".to_string(),
+		)?;
+
+		let len = string.chars().count();
+		let padding_len = usize::try_from(width).unwrap_or(0).saturating_sub(len);
+
+		// Reserving up front turns a width too large to allocate into an error
+		let mut res = String::new();
+
+		if padding_len
+			.checked_mul(fill_char.len_utf8())
+			.and_then(|padding_size| padding_size.checked_add(string.len()))
+			.is_none_or(|size| res.try_reserve_exact(size).is_err())
+		{
+			return Err(VmError::invalid_value(format!("width too large: {width}")))
+				.with_context_func_arg(self.caller_location(), name, "width".to_string());
+		}
+
+		let padding = std::iter::repeat_n(fill_char, padding_len);
+
+		if name == "pad_left" {
+			res.extend(padding);
+			res.push_str(&string);
+		} else {
+			res.push_str(&string);
+			res.extend(padding);
+		}
+
+		Ok(res.into_variant())
+	}
+
+	// Right-justifies an Integer. A "0" fill goes after the sign, like printf's `%03d`.
+	pub fn builtin_format_num(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 3)?;
+
+		let num: i64 = self.native_arg(&name, "num", args.remove(0))?;
+		let width: i64 = self.native_arg(&name, "width", args.remove(0))?;
+		let fill: String = self.native_arg(&name, "fill", args.remove(0))?;
+
+		let fill_char = single_char(&fill).with_context_func_arg(
+			self.caller_location(),
+			name.clone(),
+			"fill".to_string(),
+		)?;
+
+		let sign = if num < 0 { "-" } else { "" };
+		let digits = num.unsigned_abs().to_string();
+		let padding_len = usize::try_from(width)
+			.unwrap_or(0)
+			.saturating_sub(sign.len() + digits.len());
+
+		// Like `pad_left`, a width too large to allocate is an error rather than a panic
+		let mut res = String::new();
+
+		if padding_len
+			.checked_mul(fill_char.len_utf8())
+			.and_then(|padding_size| padding_size.checked_add(sign.len() + digits.len()))
+			.is_none_or(|size| res.try_reserve_exact(size).is_err())
+		{
+			return Err(VmError::invalid_value(format!("width too large: {width}")))
+				.with_context_func_arg(self.caller_location(), name, "width".to_string());
+		}
+
+		let padding = std::iter::repeat_n(fill_char, padding_len);
+
+		if fill_char == '0' {
+			res.push_str(sign);
+			res.extend(padding);
+		} else {
+			res.extend(padding);
+			res.push_str(sign);
+		}
+
+		res.push_str(&digits);
+
+		Ok(res.into_variant())
+	}
+
+	// Location of the statement calling `here`, as `file:line:column`.
+	pub fn builtin_here(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 0)?;
+
+		Ok(self.root_package_location.to_string().into_variant())
+	}
+
+	// Prints the variables of the current scope and of the blocks enclosing it, innermost
+	// first. The global scope is only included when `include_global` is true, or when called
+	// from it.
+	pub fn builtin_dump_scope(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		if args.len() > 1 {
+			return Err(VmError::wrong_arg_count(1, args.len()))
+				.with_context_func_call(self.caller_location(), name);
+		}
+
+		let include_global: bool = match args.pop() {
+			Some(arg) => self.native_arg(&name, "include_global", arg)?,
+			None => false,
+		};
+
+		let mut dump = String::new();
+		let mut scope = self.stack_scope.as_ref();
+
+		while let Some(current) = scope {
+			dump.push_str(&format_scope("local", current));
+			scope = current.parent.as_deref();
+		}
+
+		if include_global || self.stack_scope.is_none() {
+			dump.push_str(&format_scope("global", &self.global_scope));
+		}
+
+		if let Err(err) = write!(self.output, "{dump}") {
+			return Err(VmError::io(err)).with_context_func_call(self.caller_location(), name);
+		}
+
+		Ok(VmVariant::Unit)
+	}
+
+	pub fn builtin_args(&mut self, name: String, args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 0)?;
+
+		Ok(self.args.clone().into_variant())
+	}
+
+	// Shared by `assert_eq` and `assert_ne`. Values that can't be compared are never equal.
+	pub fn builtin_assert_eq_ne(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let lhs = args.remove(0);
+		let rhs = args.remove(0);
+
+		let equal = variants_equal(&lhs, &rhs);
+
+		let reason = match (name.as_str(), equal) {
+			("assert_eq", false) => {
+				let values = format!("{} == {}", repr(&lhs), repr(&rhs));
+				let difference = first_difference(&lhs, &rhs, String::new());
+
+				// A top-level mismatch of plain values is already obvious from the values
+				match difference.strip_prefix(": ") {
+					Some(detail) if detail == format!("{} vs {}", repr(&lhs), repr(&rhs)) => values,
+					Some(detail) => format!("{values} ({detail})"),
+					None => format!("{values} (differs at {difference})"),
+				}
+			}
+			("assert_ne", true) => format!("{} != {}", repr(&lhs), repr(&rhs)),
+			("assert_eq", true) | ("assert_ne", false) => return Ok(VmVariant::Unit),
+			_ => return Err(VmError::unknown_identifier(name).with_context_internal()),
+		};
+
+		Err(VmError::assertion_failed(reason)).with_context_func_call(self.caller_location(), name)
+	}
+
+	pub fn builtin_assert_true(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let value: bool = self.native_arg(&name, "value", args.remove(0))?;
+
+		if !value {
+			return Err(VmError::assertion_failed("expected true".to_string()))
+				.with_context_func_call(self.caller_location(), name);
+		}
+
+		Ok(VmVariant::Unit)
+	}
+
+	// Arrays are searched for an equal element, Maps for a key and Strings for a substring.
+	pub fn builtin_assert_contains(
+		&mut self,
+		name: String,
+		mut args: Vec<VmVariant>,
+	) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 2)?;
+
+		let container = args.remove(0);
+		let item = args.remove(0);
+
+		let contained = match (&container, &item) {
+			(VmVariant::Array(array), item) => array.iter().any(|elem| variants_equal(elem, item)),
+			(VmVariant::Map(map), VmVariant::String(key)) => map.contains_key(key),
+			(VmVariant::String(string), VmVariant::String(part)) => string.contains(part.as_str()),
+			(VmVariant::Map(_) | VmVariant::String(_), item) => {
+				return Err(VmError::invalid_value_type(
+					VmType::String.to_string(),
+					item.get_typeinfo().to_string(),
+				))
+				.with_context_func_arg(self.caller_location(), name, "item".to_string());
+			}
+			(container, _) => {
+				return Err(VmError::invalid_value_type(
+					"Array, Map or String".to_string(),
+					container.get_typeinfo().to_string(),
+				))
+				.with_context_func_arg(self.caller_location(), name, "container".to_string());
+			}
+		};
+
+		if !contained {
+			return Err(VmError::assertion_failed(format!(
+				"{} does not contain {}",
+				repr(&container),
+				repr(&item)
+			)))
+			.with_context_func_call(self.caller_location(), name);
+		}
+
+		Ok(VmVariant::Unit)
+	}
+
+	// Stops the script, which makes the process exit with `code`.
+	pub fn builtin_exit(&mut self, name: String, mut args: Vec<VmVariant>) -> VmResult<VmVariant> {
+		self.check_arg_count(&name, &args, 1)?;
+
+		let code: i64 = self.native_arg(&name, "code", args.remove(0))?;
+
+		let Ok(code) = i32::try_from(code) else {
+			return Err(VmError::invalid_value(format!(
+				"exit code out of range: {code}"
+			)))
+			.with_context_func_arg(self.caller_location(), name, "code".to_string());
+		};
+
+		Err(VmError::exit(code))
+	}
+}
+
+// The raw wait status on Unix, so that a signal stays distinguishable from an exit code
+#[cfg(target_family = "unix")]
+fn exit_code(status: ExitStatus) -> i32 {
+	use std::os::unix::process::ExitStatusExt;
+
+	status.into_raw()
+}
+
+#[cfg(not(target_family = "unix"))]
+fn exit_code(status: ExitStatus) -> i32 {
+	match status.code() {
+		Some(code) => code,
+		None if status.success() => 0,
+		None => 1,
+	}
+}
+
+#[cfg(target_family = "unix")]
+fn killed_by_sigpipe(status: ExitStatus) -> bool {
+	use std::os::unix::process::ExitStatusExt;
+
+	// SIGPIPE is 13 on every Unix
+	status.signal() == Some(13)
+}
+
+#[cfg(not(target_family = "unix"))]
+fn killed_by_sigpipe(_status: ExitStatus) -> bool {
+	false
+}
+
+// Output of a command, as a String unless it isn't valid UTF-8
+fn captured_output(output: Vec<u8>) -> VmVariant {
+	match String::from_utf8(output) {
+		Ok(string) => VmVariant::String(string),
+		Err(err) => VmVariant::Bytes(err.into_bytes()),
+	}
+}
+
+// Unix arguments are arbitrary bytes. Elsewhere they must still be valid UTF-8.
+#[cfg(target_family = "unix")]
+fn os_string_from_bytes(bytes: Vec<u8>) -> VmResult<OsString> {
+	use std::os::unix::ffi::OsStringExt;
+
+	Ok(OsString::from_vec(bytes))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> VmResult<OsString> {
+	String::from_utf8(bytes)
+		.map(OsString::from)
+		.map_err(|err| VmError::invalid_value(format!("not UTF-8: {err}")))
+}
+
+// One `let name = value` line per variable (`const` for constants), sorted by name
+fn format_scope(label: &str, scope: &Scope) -> String {
+	let mut names: Vec<&String> = scope.variables.keys().collect();
+
+	names.sort_unstable();
+
+	let mut dump = format!("{label}:\n");
+
+	for name in names {
+		let stored = &scope.variables[name];
+		let keyword = if stored.constant { "const" } else { "let" };
+
+		dump.push_str(&format!("  {keyword} {name} = {}\n", stored.value));
+	}
+
+	dump
+}
+
+// Like Display, but with strings quoted so `1` and `"1"` can be told apart
+fn repr(value: &VmVariant) -> String {
+	match value {
+		VmVariant::String(string) => format!("{string:?}"),
+		VmVariant::Array(array) => {
+			let elems: Vec<String> = array.iter().map(repr).collect();
+
+			format!("[{}]", elems.join(", "))
+		}
+		VmVariant::Map(map) => {
+			let entries: Vec<String> = map
+				.iter()
+				.map(|(key, value)| format!("{key:?}: {}", repr(value)))
+				.collect();
+
+			format!("{{{}}}", entries.join(", "))
+		}
+		VmVariant::Ref(value) => repr(value),
+		value => value.to_string(),
+	}
+}
+
+fn variants_equal(lhs: &VmVariant, rhs: &VmVariant) -> bool {
+	lhs.equals(rhs) == Some(true)
+}
+
+// Describes where two unequal values first differ, walking into arrays and maps so a mismatch
+// deep inside a big value is still easy to spot.
+fn first_difference(lhs: &VmVariant, rhs: &VmVariant, path: String) -> String {
+	match (lhs, rhs) {
+		(VmVariant::Ref(lhs), rhs) => first_difference(lhs, rhs, path),
+		(lhs, VmVariant::Ref(rhs)) => first_difference(lhs, rhs, path),
+		(VmVariant::Array(lhs_array), VmVariant::Array(rhs_array)) => {
+			for (idx, (lhs_elem, rhs_elem)) in lhs_array.iter().zip(rhs_array).enumerate() {
+				if !variants_equal(lhs_elem, rhs_elem) {
+					return first_difference(lhs_elem, rhs_elem, format!("{path}[{idx}]"));
+				}
+			}
+
+			format!("{path}: length {} vs {}", lhs_array.len(), rhs_array.len())
+		}
+		(VmVariant::Map(lhs_map), VmVariant::Map(rhs_map)) => {
+			for (key, lhs_value) in lhs_map {
+				match rhs_map.get(key) {
+					None => return format!("{path}.{key}: missing on the right"),
+					Some(rhs_value) if !variants_equal(lhs_value, rhs_value) => {
+						return first_difference(lhs_value, rhs_value, format!("{path}.{key}"));
+					}
+					Some(_) => (),
+				}
+			}
+
+			match rhs_map.keys().find(|key| !lhs_map.contains_key(*key)) {
+				Some(key) => format!("{path}.{key}: missing on the left"),
+				None => format!("{path}: {} vs {}", repr(lhs), repr(rhs)),
+			}
+		}
+		(lhs, rhs) => format!("{path}: {} vs {}", repr(lhs), repr(rhs)),
+	}
+}
+
+fn compare_variants(lhs: &VmVariant, rhs: &VmVariant) -> VmResult<Ordering> {
+	lhs.compare(rhs).ok_or_else(|| {
+		VmError::invalid_comparison(
+			lhs.get_typeinfo().to_string(),
+			rhs.get_typeinfo().to_string(),
+		)
+	})
+}
+
+// Stable merge sort with a fallible comparator: std's sort can't bail out on an error, and may
+// panic when the comparator isn't a total order (which a user-provided function can't promise).
+fn try_sort_by<F>(array: Vec<VmVariant>, cmp: &mut F) -> VmResult<Vec<VmVariant>>
+where
+	F: FnMut(&VmVariant, &VmVariant) -> VmResult<Ordering>,
+{
+	if array.len() <= 1 {
+		return Ok(array);
+	}
+
+	let mut left = array;
+	let right = left.split_off(left.len() / 2);
+
+	let left = try_sort_by(left, cmp)?;
+	let right = try_sort_by(right, cmp)?;
+
+	let mut merged = Vec::with_capacity(left.len() + right.len());
+	let mut left = left.into_iter().peekable();
+	let mut right = right.into_iter().peekable();
+
+	while let (Some(lhs), Some(rhs)) = (left.peek(), right.peek()) {
+		if cmp(lhs, rhs)? == Ordering::Greater {
+			merged.extend(right.next());
+		} else {
+			merged.extend(left.next());
+		}
+	}
+
+	merged.extend(left);
+	merged.extend(right);
+
+	Ok(merged)
+}
+
+pub(super) fn time_seed() -> u64 {
+	let nanos = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |elapsed| elapsed.as_nanos());
+
+	non_zero_seed(nanos as u64)
+}
+
+// A zero state would make xorshift output zeros forever
+fn non_zero_seed(seed: u64) -> u64 {
+	if seed == 0 {
+		0x9E37_79B9_7F4A_7C15
+	} else {
+		seed
+	}
+}
+
+fn xorshift64(state: &mut u64) -> u64 {
+	let mut x = *state;
+
+	x ^= x << 13;
+	x ^= x >> 7;
+	x ^= x << 17;
+
+	*state = x;
+	x
+}
+
+fn flatten(array: Vec<VmVariant>) -> Vec<VmVariant> {
+	let mut flattened = Vec::with_capacity(array.len());
+
+	for elem in array {
+		match elem {
+			VmVariant::Array(inner) => flattened.extend(inner),
+			elem => flattened.push(elem),
+		}
+	}
+
+	flattened
+}
+
+fn single_char(string: &str) -> VmResult<char> {
+	let mut chars = string.chars();
+
+	match (chars.next(), chars.next()) {
+		(Some(c), None) => Ok(c),
+		_ => Err(VmError::invalid_value(format!(
+			"expected a single character, got {string:?}"
+		))),
+	}
+}
+
+// Strips a `0x`, `0o` or `0b` prefix (after the sign, if any) and returns the radix it stands for
+fn split_radix_prefix(string: &str) -> (String, u32) {
+	let (sign, unsigned) = match string.strip_prefix('-') {
+		Some(unsigned) => ("-", unsigned),
+		None => ("", string.strip_prefix('+').unwrap_or(string)),
+	};
+
+	for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+		// A sign after the prefix (`0x-1`) is left in place for parsing to reject
+		if let Some(digits) = unsigned.strip_prefix(prefix) {
+			if !digits.starts_with(['+', '-']) {
+				return (format!("{sign}{digits}"), radix);
+			}
+		}
+	}
+
+	(string.to_string(), 10)
+}
+
+fn native_index(index: i64, len: usize) -> VmResult<usize> {
+	usize::try_from(index).map_err(|_| VmError::index_out_of_bounds(index, len))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use crate::vm::{
+		testing::{assert_evals_to, eval_err, exec, temp_dir, test_vm},
+		variant::VmVariant,
+	};
+
+	#[test]
+	fn slice_bounds() {
+		assert_evals_to("slice([1, 2, 3], 0, 3)", "[1, 2, 3]");
+		assert_evals_to("slice([1, 2, 3], 1, 2)", "[2]");
+		assert_evals_to("slice([1, 2, 3], 3, 3)", "[]");
+		assert_evals_to("slice([1, 2, 3], 2, 1)", "[]");
+		assert_evals_to("slice([1, 2, 3], 1, 10)", "[2, 3]");
+		assert_evals_to("slice([], 0, 0)", "[]");
+
+		assert!(eval_err("slice([1, 2, 3], 4, 5)").contains("index out of bounds"));
+		assert!(eval_err("slice([1, 2, 3], -1, 2)").contains("at argument start"));
+		assert!(eval_err("slice([1, 2, 3], 0, -1)").contains("at argument end"));
+	}
+
+	#[test]
+	fn top_level_builtin_errors_are_located() {
+		assert!(eval_err("sum(5)").starts_with("test:1:"));
+	}
+
+	#[test]
+	fn sort_integers_and_strings() {
+		assert_evals_to("sort([3, 1, 2])", "[1, 2, 3]");
+		assert_evals_to(
+			r#"sort(["pear", "apple", "fig"])"#,
+			r#"["apple", "fig", "pear"]"#,
+		);
+		assert_evals_to("reverse([1, 2, 3])", "[3, 2, 1]");
+		assert!(eval_err(r#"sort([1, "a"])"#).contains("cannot compare"));
+	}
+
+	#[test]
+	fn sort_by_is_stable() {
+		assert_evals_to(
+			r#"
+				fn by_first(a, b) {
+					if a[0] < b[0] { -1 } else { if a[0] > b[0] { 1 } else { 0 } }
+				}
+				sort_by([[1, "b"], [0, "z"], [1, "a"]], "by_first")
+			"#,
+			r#"[[0, "z"], [1, "b"], [1, "a"]]"#,
+		);
+	}
+
+	#[test]
+	fn min_max_sum() {
+		assert_evals_to("min([3, -1, 2])", "-1");
+		assert_evals_to("max([3, -1, 2])", "3");
+		assert_evals_to("sum([3, -1, 2])", "4");
+		assert_evals_to("sum([])", "0");
+
+		assert!(eval_err("min([])").contains("empty array"));
+		assert!(eval_err("max([])").contains("empty array"));
+		assert!(eval_err(r#"sum([1, "2"])"#).contains("sum"));
+	}
+
+	#[test]
+	fn functions_cannot_shadow_builtins() {
+		assert!(eval_err("fn sum(a) { a }").contains("duplicate function: sum"));
+	}
+
+	#[test]
+	fn integer_math() {
+		assert_evals_to("pow(2, 10)", "1024");
+		assert_evals_to("pow(5, 0)", "1");
+		assert_evals_to("pow(-1, 5000000000)", "1");
+		assert_evals_to("abs(-7)", "7");
+		assert_evals_to("isqrt(17)", "4");
+
+		assert!(eval_err("pow(2, 63)").contains("integer overflow"));
+		assert!(eval_err("pow(3, 5000000000)").contains("integer overflow"));
+		assert!(eval_err(r#"abs(to_int("-9223372036854775808"))"#).contains("integer overflow"));
+		assert!(eval_err("pow(2, -1)").contains("negative exponent"));
+		assert!(eval_err("isqrt(-4)").contains("negative number"));
+	}
+
+	#[test]
+	fn scalar_min_max_clamp() {
+		assert_evals_to("clamp(15, 0, 10)", "10");
+		assert_evals_to("clamp(-3, 0, 10)", "0");
+		assert_evals_to("clamp(4, 0, 10)", "4");
+		assert_evals_to("min(3, 5)", "3");
+		assert_evals_to(r#"max("a", "b")"#, r#""b""#);
+
+		assert!(eval_err("clamp(1, 10, 0)").contains("greater than upper bound"));
+		assert!(eval_err(r#"min(1, "a")"#).contains("cannot compare"));
+	}
+
+	#[test]
+	fn type_predicates() {
+		let values = [
+			("unit", r#"from_json("null")"#),
+			("bool", "true"),
+			("int", "1"),
+			("string", r#""s""#),
+			("array", "[1]"),
+			("map", "{}"),
+			("bytes", "bytes([1])"),
+		];
+
+		for (predicate, _) in values {
+			for (kind, value) in values {
+				let expected = if predicate == kind { "true" } else { "false" };
+
+				assert_evals_to(&format!("is_{predicate}({value})"), expected);
+			}
+		}
+
+		assert!(eval_err("is_int()").contains("is_int"));
+	}
+
+	#[test]
+	fn display_array_separators() {
+		assert_evals_to(r#"display_array([1, 2, 3], "", "|", "")"#, r#""1|2|3""#);
+		assert_evals_to(r#"display_array([1, "a"], "<", ", ", ">")"#, r#""<1, a>""#);
+		assert_evals_to(r#"display_array([], "[", ",", "]")"#, r#""[]""#);
+		assert_evals_to(r#"display_array([[1, 2]], "", "|", "")"#, r#""[1, 2]""#);
+	}
+
+	#[test]
+	fn read_all_from_input() {
+		let (mut vm, _) = test_vm();
+
+		assert!(exec(&mut vm, "read_all();").is_err());
+
+		vm.set_input(Box::new(Cursor::new("first line\nsecond line\n")));
+		exec(&mut vm, "let input = read_all(); let rest = read_all();").unwrap();
+
+		assert_eq!(
+			vm.get_global("input").unwrap().to_string(),
+			"first line\nsecond line\n"
+		);
+		assert_eq!(vm.get_global("rest").unwrap().to_string(), "");
+	}
+
+	#[test]
+	fn now_and_sleep() {
+		assert_evals_to("now() > 0", "true");
+		assert_evals_to("typename(sleep(0))", r#""Unit""#);
+		assert_evals_to("let before = now(); sleep(5); now() > before", "true");
+		assert!(eval_err("sleep(-1)").contains("negative duration"));
+	}
+
+	#[test]
+	fn seeded_random_is_deterministic() {
+		let draws = "seed(42); [random(0, 1000), random(0, 1000), random(-5, 5)]";
+
+		assert_evals_to(
+			&format!("let a = {{ {draws} }}; let b = {{ {draws} }}; a == b"),
+			"true",
+		);
+		assert_evals_to("seed(7); let x = random(3, 4); x", "3");
+		assert!(eval_err("random(5, 5)").contains("empty range"));
+	}
+
+	#[test]
+	fn callbacks_keep_the_caller_scope() {
+		let (mut vm, _) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"
+				let suffix = "!";
+				fn shout(word) { [word, suffix] }
+				fn run(words) {
+					let local = "kept";
+					let shouted = map(words, "shout");
+					[shouted, local, suffix]
+				}
+				let res = run(["a", "b"]);
+			"#,
+		)
+		.unwrap();
+		assert_eq!(
+			vm.get_global("res").unwrap().to_string(),
+			"[[[a, !], [b, !]], kept, !]"
+		);
+
+		// An error in the callback doesn't leave the VM inside the callback's scope
+		let err = exec(
+			&mut vm,
+			"fn fail(x) { missing }\nlet r = map([1], \"fail\");",
+		)
+		.unwrap_err()
+		.to_string();
+		assert!(
+			err.starts_with("test:1:14: unknown identifier: missing"),
+			"{err}"
+		);
+		exec(&mut vm, "let after = suffix;").unwrap();
+		assert_eq!(vm.get_global("after").unwrap().to_string(), "!");
+
+		assert!(eval_err(r#"map([1], "nope")"#).starts_with("test:1:"));
+	}
+
+	#[test]
+	fn count_occurrences() {
+		assert_evals_to(r#"count([1, "a", 1, [1], "1"], 1)"#, "2");
+		assert_evals_to(r#"count([1, "a", 1, "a", "b"], "a")"#, "2");
+		assert_evals_to(r#"count([1, "a"], "z")"#, "0");
+		assert_evals_to("count([], 1)", "0");
+		assert_evals_to(
+			r#"frequencies([1, "a", 1, "b", "a", 1])"#,
+			r#"{ "1": 3, "a": 2, "b": 1 }"#,
+		);
+	}
+
+	#[test]
+	fn zip_arrays() {
+		assert_evals_to(r#"zip([1, 2], ["a", "b"])"#, r#"[[1, "a"], [2, "b"]]"#);
+		assert_evals_to(r#"zip([1, 2, 3], ["a"])"#, r#"[[1, "a"]]"#);
+		assert_evals_to("zip([], [1, 2])", "[]");
+		assert_evals_to(r#"enumerate(["a", "b"])"#, r#"[[0, "a"], [1, "b"]]"#);
+	}
+
+	#[test]
+	fn flatten_one_level() {
+		assert_evals_to("flatten([[1, 2], [3]])", "[1, 2, 3]");
+		assert_evals_to(r#"flatten([1, [2, [3]], "a", []])"#, r#"[1, 2, [3], "a"]"#);
+		assert_evals_to("flatten([])", "[]");
+		assert_evals_to(
+			r#"fn twice(x) { [x, x] } flat_map([1, 2], "twice")"#,
+			"[1, 1, 2, 2]",
+		);
+	}
+
+	#[test]
+	fn unique_elements() {
+		assert_evals_to("unique([3, 1, 3, 2, 1])", "[3, 1, 2]");
+		assert_evals_to(r#"unique(["b", "a", "b"])"#, r#"["b", "a"]"#);
+		// Elements of different types never compare equal, so `1` and `"1"` both stay
+		assert_evals_to(r#"unique([1, "1", 1, [1], [1]])"#, r#"[1, "1", [1]]"#);
+		assert_evals_to("unique([])", "[]");
+	}
+
+	#[test]
+	fn repeat_strings_and_arrays() {
+		assert_evals_to(r#"repeat("ab", 3)"#, r#""ababab""#);
+		assert_evals_to("repeat([0], 3)", "[0, 0, 0]");
+		assert_evals_to(r#"repeat("ab", 0)"#, r#""""#);
+		assert_evals_to("repeat([1, 2], 2)", "[1, 2, 1, 2]");
+
+		assert!(eval_err(r#"repeat("ab", -1)"#).contains("negative count"));
+		assert!(eval_err(r#"repeat("ab", 9223372036854775807)"#).contains("result too large"));
+		assert!(eval_err("repeat([1], 9223372036854775807)").contains("result too large"));
+		assert_evals_to(r#"repeat("", 9223372036854775807)"#, r#""""#);
+		assert!(eval_err("repeat(1, 2)").contains("String or Array"));
+	}
+
+	#[test]
+	fn starts_and_ends_with() {
+		assert_evals_to(r#"starts_with("hello", "he")"#, "true");
+		assert_evals_to(r#"starts_with("hello", "lo")"#, "false");
+		assert_evals_to(r#"ends_with("hello", "lo")"#, "true");
+		assert_evals_to(r#"starts_with("hello", "")"#, "true");
+		assert_evals_to(r#"ends_with("", "")"#, "true");
+		assert_evals_to(r#"starts_with("", "a")"#, "false");
+		// A prefix ending inside a multi-byte character must not split it
+		assert_evals_to(r#"starts_with("été", "é")"#, "true");
+		assert_evals_to(r#"starts_with("été", "e")"#, "false");
+		assert_evals_to(r#"ends_with("日本語", "語")"#, "true");
+		assert_evals_to(r#"ends_with("a", "日本語")"#, "false");
+	}
+
+	#[test]
+	fn pad_strings() {
+		assert_evals_to(r#"pad_left("7", 3, "0")"#, r#""007""#);
+		assert_evals_to(r#"pad_right("ab", 4, ".")"#, r#""ab..""#);
+		// Never truncates
+		assert_evals_to(r#"pad_left("abcdef", 3, " ")"#, r#""abcdef""#);
+		assert_evals_to(r#"pad_right("abc", -1, " ")"#, r#""abc""#);
+		// Widths count characters, for the string and the fill alike
+		assert_evals_to(r#"pad_left("é", 3, "─")"#, r#""──é""#);
+		assert_evals_to(r#"pad_right("日本", 4, "語")"#, r#""日本語語""#);
+
+		assert!(eval_err(r#"pad_left("a", 3, "ab")"#).contains("at argument fill"));
+		assert!(eval_err(r#"pad_left("a", 9223372036854775807, "x")"#).contains("width too large"));
+	}
+
+	#[test]
+	fn println_writes_to_the_output_sink() {
+		let (mut vm, output) = test_vm();
+
+		exec(&mut vm, r#"println("hello", 1, [true, "x"]); println();"#).unwrap();
+
+		assert_eq!(output.contents(), "hello 1 [true, x]\n\n");
+	}
+
+	#[test]
+	fn format_numbers() {
+		assert_evals_to(r#"format_num(7, 3, "0")"#, r#""007""#);
+		assert_evals_to(r#"format_num(-7, 4, "0")"#, r#""-007""#);
+		assert_evals_to(r#"format_num(-7, 4, " ")"#, r#""  -7""#);
+		assert_evals_to(r#"format_num(12345, 3, "0")"#, r#""12345""#);
+		assert_evals_to(
+			r#"format_num(-9223372036854775807, 0, "0")"#,
+			r#""-9223372036854775807""#,
+		);
+
+		assert!(eval_err(r#"format_num(1, 3, "")"#).contains("at argument fill"));
+		assert!(eval_err(r#"format_num(1, 9223372036854775807, "0")"#).contains("width too large"));
+	}
+
+	#[test]
+	fn env_all_lists_variables() {
+		// Setting a variable would race with the tests spawning processes
+		let (mut vm, _) = test_vm();
+
+		vm.set_global(
+			"path".to_string(),
+			VmVariant::String(std::env::var("PATH").unwrap()),
+		);
+		exec(&mut vm, r#"assert_eq(env_all()["PATH"], path);"#).unwrap();
+
+		assert_evals_to(r#"typename(env_all())"#, r#""Map""#);
+		assert!(eval_err("env_all(1)").contains("argument"));
+	}
+
+	// Changing directories is tested from tests/cli.rs, as the working directory belongs to the
+	// whole test process
+	#[test]
+	fn chdir_rejects_missing_directories() {
+		assert!(eval_err(r#"chdir("/nonexistent/ulesl")"#).contains("path"));
+	}
+
+	#[test]
+	fn list_dir_is_sorted() {
+		let dir = temp_dir("list_dir");
+		for name in ["b.txt", "a.txt", "c"] {
+			std::fs::write(dir.join(name), "").unwrap();
+		}
+		let (mut vm, _) = test_vm();
+
+		vm.set_global(
+			"dir".to_string(),
+			VmVariant::String(dir.to_string_lossy().into_owned()),
+		);
+		exec(&mut vm, "let entries = list_dir(dir);").unwrap();
+
+		assert_eq!(
+			vm.get_global("entries").unwrap().to_string(),
+			"[a.txt, b.txt, c]"
+		);
+		assert!(eval_err(r#"list_dir("/nonexistent/ulesl")"#).contains("path"));
+	}
+
+	#[test]
+	fn path_metadata() {
+		let dir = temp_dir("metadata");
+		std::fs::write(dir.join("file.txt"), "12345").unwrap();
+		let (mut vm, _) = test_vm();
+
+		for (name, path) in [
+			("dir", dir.clone()),
+			("file", dir.join("file.txt")),
+			("missing", dir.join("missing")),
+		] {
+			vm.set_global(
+				name.to_string(),
+				VmVariant::String(path.to_string_lossy().into_owned()),
+			);
+		}
+		exec(
+			&mut vm,
+			"let checks = [exists(file), is_file(file), is_dir(file), is_dir(dir)];
+			let missing_checks = [exists(missing), is_file(missing), is_dir(missing)];
+			let size = file_size(file);",
+		)
+		.unwrap();
+
+		assert_eq!(
+			vm.get_global("checks").unwrap().to_string(),
+			"[true, true, false, true]"
+		);
+		assert_eq!(
+			vm.get_global("missing_checks").unwrap().to_string(),
+			"[false, false, false]"
+		);
+		assert_eq!(vm.get_global("size").unwrap().to_string(), "5");
+		assert!(exec(&mut vm, "file_size(missing);").is_err());
+	}
+
+	#[test]
+	fn collect_values() {
+		assert_evals_to(r#"collect("héj")"#, r#"["h", "é", "j"]"#);
+		assert_evals_to(r#"collect("")"#, "[]");
+		assert_evals_to(r#"collect([1, "a"])"#, r#"[1, "a"]"#);
+		assert_evals_to(r#"collect({ "b": 2, "a": 1 })"#, r#"[["a", 1], ["b", 2]]"#);
+		assert!(eval_err("collect(1)").contains("Array, Map or String"));
+	}
+
+	#[test]
+	fn time_call_returns_result_and_ms() {
+		let (mut vm, _) = test_vm();
+
+		exec(
+			&mut vm,
+			"fn nap(a, b) { sleep(30); b }
+			let timed = time_call(\"nap\", 1, [2]);
+			let sorted = time_call(\"sort\", [3, 1, 2]);",
+		)
+		.unwrap();
+
+		for name in ["timed", "sorted"] {
+			let Some(VmVariant::Map(timing)) = vm.get_global(name) else {
+				panic!("{name} should be a map");
+			};
+
+			assert_eq!(timing.keys().collect::<Vec<_>>(), ["ms", "result"]);
+			assert!(matches!(timing["ms"], VmVariant::Integer(ms) if ms >= 0));
+		}
+		exec(
+			&mut vm,
+			"assert_eq(timed.result, [2]); assert_eq(sorted.result, [1, 2, 3]);",
+		)
+		.unwrap();
+
+		let Some(VmVariant::Map(timed)) = vm.get_global("timed") else {
+			panic!("timed should be a map");
+		};
+		assert!(matches!(timed["ms"], VmVariant::Integer(ms) if ms >= 30));
+
+		// Errors from the called function propagate as they are
+		assert_eq!(
+			eval_err("fn fails() { assert_eq(1, 2) } time_call(\"fails\")"),
+			concat!(
+				"test:1:47: in assert_eq: assertion failed: 1 == 2\n",
+				"  in fails (called at test:1:47)"
+			)
+		);
+		assert!(exec(&mut vm, "time_call(\"missing\");").is_err());
+		assert!(exec(&mut vm, "time_call();").is_err());
+	}
+
+	#[test]
+	fn assertion_messages() {
+		assert_eq!(
+			eval_err(r#"assert_eq({ "a": 1, "b": [1, "x"] }, { "a": 1, "b": [1, "y"] })"#),
+			concat!(
+				r#"test:1:16: in assert_eq: assertion failed: {"a": 1, "b": [1, "x"]} == "#,
+				r#"{"a": 1, "b": [1, "y"]} (differs at .b[1]: "x" vs "y")"#,
+			)
+		);
+		assert!(eval_err(r#"assert_eq({ "a": 1 }, { "a": 1, "c": 2 })"#)
+			.ends_with("(differs at .c: missing on the left)"));
+		assert!(eval_err(r#"assert_eq(1, "1")"#).ends_with(r#"assertion failed: 1 == "1""#));
+		assert!(eval_err("assert_ne([1], [1])").ends_with("assertion failed: [1] != [1]"));
+		assert!(eval_err("assert_true(false)").ends_with("assertion failed: expected true"));
+		assert!(eval_err("assert_contains([1], 2)").ends_with("[1] does not contain 2"));
+
+		assert_evals_to(
+			r#"assert_eq({ "a": [1] }, { "a": [1] });
+			assert_ne(1, 2);
+			assert_contains("abc", "b");
+			1"#,
+			"1",
+		);
+	}
+
+	#[test]
+	fn key_value_lines() {
+		assert_evals_to(r#"to_kv({ "y": 2, "x": 1 }, "\n")"#, r#""x=1\ny=2""#);
+		assert_evals_to(r#"to_kv({ "a": "b", "c": [1] }, ";")"#, r#""a=b;c=[1]""#);
+		assert_evals_to(r#"to_kv({}, "\n")"#, r#""""#);
+		assert!(eval_err(r#"to_kv([1], "\n")"#).contains("map"));
+	}
+
+	#[test]
+	fn split_lines() {
+		assert_evals_to(r#"lines("a\nb\n")"#, r#"["a", "b"]"#);
+		assert_evals_to(r#"lines("a\nb")"#, r#"["a", "b"]"#);
+		assert_evals_to(r#"lines("a\r\nb\r\n")"#, r#"["a", "b"]"#);
+		assert_evals_to(r#"lines("a\n\nb")"#, r#"["a", "", "b"]"#);
+		assert_evals_to(r#"lines("")"#, "[]");
+		assert!(eval_err("lines(1)").contains("string"));
+	}
+
+	#[test]
+	fn read_lines_of_a_file() {
+		let path = temp_dir("read_lines").join("lines.txt");
+		std::fs::write(&path, "first\nsecond\r\n\nlast").unwrap();
+		let (mut vm, _) = test_vm();
+
+		vm.set_global(
+			"path".to_string(),
+			VmVariant::String(path.to_string_lossy().into_owned()),
+		);
+		exec(
+			&mut vm,
+			r#"assert_eq(read_lines(path), ["first", "second", "", "last"]);"#,
+		)
+		.unwrap();
+
+		assert!(eval_err(r#"read_lines("/nonexistent/ulesl")"#).contains("path"));
+	}
+
+	#[test]
+	fn parse_integers_in_any_radix() {
+		assert_evals_to(r#"to_int("ff", 16)"#, "255");
+		assert_evals_to(r#"to_int("-101", 2)"#, "-5");
+		assert_evals_to(r#"to_int("777", 8)"#, "511");
+		assert_evals_to(r#"to_int("zz", 36)"#, "1295");
+		assert_evals_to(r#"to_int("42")"#, "42");
+		assert_evals_to(
+			r#"[to_int("0x1F"), to_int("-0b11"), to_int("0o17")]"#,
+			"[31, -3, 15]",
+		);
+
+		assert!(eval_err(r#"to_int("12", 2)"#).ends_with(r#"invalid integer in base 2: "12""#));
+		assert!(eval_err(r#"to_int("0xfg")"#).ends_with(r#"invalid integer in base 16: "0xfg""#));
+		assert!(eval_err(r#"to_int("0x-1")"#).contains("invalid integer"));
+		assert!(eval_err(r#"to_int("1", 37)"#).contains("radix must be between 2 and 36"));
+		assert!(eval_err(r#"to_int("8000000000000000", 16)"#).contains("integer overflow"));
+	}
+
+	#[test]
+	fn char_codes() {
+		assert_evals_to(r#"ord("A")"#, "65");
+		assert_evals_to(r#"ord("é")"#, "233");
+		assert_evals_to("chr(65)", r#""A""#);
+		assert_evals_to(r#"chr(ord("€"))"#, r#""€""#);
+
+		// 0x110000, one past the last code point, and a surrogate
+		assert!(eval_err("chr(1114112)").contains("not a valid code point: 1114112"));
+		assert!(eval_err("chr(55296)").contains("not a valid code point"));
+		assert!(eval_err("chr(-1)").contains("not a valid code point"));
+		assert!(eval_err(r#"ord("ab")"#).contains("char"));
+		assert!(eval_err(r#"ord("")"#).contains("char"));
+	}
+
+	#[test]
+	fn trim_family() {
+		assert_evals_to(r#"trim(" \t a b \n")"#, r#""a b""#);
+		assert_evals_to(r#"trim_start("  a  ")"#, r#""a  ""#);
+		assert_evals_to(r#"trim_end("  a  ")"#, r#""  a""#);
+		assert_evals_to(r#"trim_matches("-=x=-y-", "=-")"#, r#""x=-y""#);
+		assert_evals_to(r#"trim_matches("abc", "xyz")"#, r#""abc""#);
+		assert_evals_to(r#"trim_matches("aaa", "a")"#, r#""""#);
+		assert_evals_to(r#"trim_matches("abc", "")"#, r#""abc""#);
+
+		assert!(eval_err("trim(1)").contains("string"));
+		assert!(eval_err(r#"trim_matches("abc", 1)"#).contains("chars"));
+		assert!(eval_err(r#"trim_matches(["a"], "a")"#).contains("string"));
+	}
+
+	#[test]
+	fn expect_type_guards_values() {
+		assert_evals_to(r#"expect_type("a", "String")"#, r#""a""#);
+		assert_evals_to(r#"expect_type([1], "Array")"#, "[1]");
+		assert_evals_to(r#"expect_type({}, "Vary")"#, "{}");
+
+		assert!(eval_err(r#"expect_type(1, "String")"#)
+			.ends_with("at argument value: unexpected type (expected String, got Integer)"));
+		assert!(eval_err(r#"expect_type(1, "Strin")"#).ends_with("unknown type: Strin"));
+
+		let (mut vm, _) = test_vm();
+		exec(
+			&mut vm,
+			r#"fn greet(who) { expect_type(who, "String"); who }"#,
+		)
+		.unwrap();
+		assert!(exec(&mut vm, r#"greet("you");"#).is_ok());
+		assert!(exec(&mut vm, "greet(1);").is_err());
+	}
+
+	#[test]
+	fn dynamic_fields() {
+		assert_evals_to(r#"let f = "b"; get_field({ "a": 1, "b": [2] }, f)"#, "[2]");
+		assert!(eval_err(r#"get_field({ "a": 1 }, "z")"#).contains("z"));
+
+		let (mut vm, _) = test_vm();
+		exec(
+			&mut vm,
+			r#"let point = { "x": 1, "y": 2 };
+			let copy = point;
+			set_field("point", "y", [3]);"#,
+		)
+		.unwrap();
+
+		assert_eq!(
+			vm.get_global("point").unwrap().to_string(),
+			"{x: 1, y: [3]}"
+		);
+		// The update goes to the variable, not to the copies of its value
+		assert_eq!(vm.get_global("copy").unwrap().to_string(), "{x: 1, y: 2}");
+
+		assert!(exec(&mut vm, r#"set_field("point", "z", 1);"#).is_err());
+		assert!(exec(&mut vm, r#"set_field("missing", "x", 1);"#).is_err());
+		assert!(exec(
+			&mut vm,
+			r#"const fixed = { "x": 1 }; set_field("fixed", "x", 2);"#
+		)
+		.is_err());
+	}
+
+	#[test]
+	fn here_is_the_calling_statement() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			"let top = here();\nfn where() {\n\there()\n}\n\tprintln(here(), where());",
+		)
+		.unwrap();
+
+		assert_eq!(vm.get_global("top").unwrap().to_string(), "test:1:1");
+		assert_eq!(output.contents(), "test:5:2 test:3:2\n");
+		assert!(eval_err("here(1)").contains("argument"));
+	}
+
+	#[test]
+	fn apply_spreads_arguments() {
+		let (mut vm, _) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"fn add(a, b) { [a, b] }
+			assert_eq(apply("add", [1, 2]), add(1, 2));
+			assert_eq(apply("typename", ["a"]), typename("a"));
+			assert_eq(apply("args", []), args());"#,
+		)
+		.unwrap();
+
+		assert!(exec(&mut vm, r#"apply("add", [1]);"#)
+			.unwrap_err()
+			.to_string()
+			.contains("add"));
+		assert!(exec(&mut vm, r#"apply("missing", []);"#).is_err());
+		assert!(exec(&mut vm, r#"apply("add", 1);"#).is_err());
+	}
+
+	#[test]
+	fn entries_round_trip() {
+		assert_evals_to(
+			r#"entries({ "b": [2], "a": 1 })"#,
+			r#"[["a", 1], ["b", [2]]]"#,
+		);
+		assert_evals_to(
+			r#"let m = { "b": [2], "a": { "c": 3 } }; from_entries(entries(m)) == m"#,
+			"true",
+		);
+		assert_evals_to(r#"from_entries([["k", 1]])"#, r#"{ "k": 1 }"#);
+		assert_evals_to("entries({})", "[]");
+
+		assert!(eval_err(r#"from_entries([["a", 1], ["a", 2]])"#).contains("duplicate key: a"));
+		assert!(eval_err("from_entries([[1, 2]])").contains("pairs"));
+		assert!(eval_err(r#"from_entries([["a"]])"#).contains("got 1 elements"));
+	}
+
+	#[test]
+	fn dump_scope_lists_variables() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"let g = 1;
+			const k = [2];
+			fn f(a) { let b = [a]; { let c = "x"; dump_scope(); } dump_scope(true); }
+			f(3);"#,
+		)
+		.unwrap();
+		assert_eq!(
+			output.contents(),
+			concat!(
+				"local:\n  let c = x\n",
+				"local:\n  let a = 3\n  let b = [3]\n",
+				"local:\n  let a = 3\n  let b = [3]\n",
+				"global:\n  let g = 1\n  const k = [2]\n",
+			)
+		);
+
+		let (mut vm, output) = test_vm();
+		exec(&mut vm, "let only = 1; dump_scope();").unwrap();
+		assert_eq!(output.contents(), "global:\n  let only = 1\n");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn raw_byte_arguments() {
+		let (mut vm, _) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"let out = exec_capture([], "printf", "%s", bytes([97, 255, 98])).stdout;"#,
+		)
+		.unwrap();
+		assert!(matches!(
+			vm.get_global("out"),
+			Some(VmVariant::Bytes(bytes)) if bytes == b"a\xffb"
+		));
+
+		// No platform can pass a NUL inside an argument: it's an error, not a truncated argument
+		assert_eq!(
+			eval_err(r#"exec_capture([], "printf", "%s", bytes([97, 0, 98]))"#),
+			"test:1:16: in exec_capture: I/O error: nul byte found in provided data"
+		);
+		assert!(eval_err(r#"exec_capture("printf", "%s")"#).contains("at argument exec_opt"));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn spawn_and_kill() {
+		let (mut vm, _) = test_vm();
+		let start = std::time::Instant::now();
+
+		exec(
+			&mut vm,
+			r#"let sleeper = spawn([], "sleep", "30");
+			let quick = spawn([], "true");
+			kill(sleeper);
+			let quick_status = wait(quick);"#,
+		)
+		.unwrap();
+
+		assert!(start.elapsed() < std::time::Duration::from_secs(10));
+		assert_eq!(vm.get_global("quick_status").unwrap().to_string(), "0");
+		// Once killed or waited for, a process is no longer tracked
+		assert!(exec(&mut vm, "wait(sleeper);")
+			.unwrap_err()
+			.to_string()
+			.contains("no spawned process with pid"));
+		assert!(exec(&mut vm, "kill(quick);").is_err());
+		assert!(eval_err("kill(-1)").contains("no spawned process with pid -1"));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn pipe_stages() {
+		assert_evals_to(
+			r#"pipe(["echo", "hello"], ["tr", "a-z", "A-Z"])"#,
+			r#""HELLO\n""#,
+		);
+		assert_evals_to(
+			r#"pipe(["printf", "b\na\n"], ["sort"], ["head", "-n1"])"#,
+			r#""a\n""#,
+		);
+		// `yes` never ends by itself: it's killed by SIGPIPE once `head` is done
+		assert_evals_to(r#"pipe(["yes"], ["head", "-n1"])"#, r#""y\n""#);
+
+		assert_eq!(
+			eval_err(r#"pipe(["sh", "-c", "exit 3"], ["cat"])"#),
+			"test:1:16: in pipe, at argument stage0 (sh): command failed (exit status: 3)"
+		);
+		assert!(eval_err(r#"pipe(["echo"], ["sh", "-c", "kill -PIPE $$"])"#)
+			.contains("at argument stage1 (sh): command failed (signal: 13"));
+		assert!(eval_err(r#"pipe(["echo"], ["/nonexistent/ulesl"])"#)
+			.contains("at argument stage1 (/nonexistent/ulesl): I/O error"));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn exec_with_input_feeds_stdin() {
+		assert_evals_to(
+			r#"exec_with_input("hello\nworld\n", "cat")"#,
+			r#""hello\nworld\n""#,
+		);
+		assert_evals_to(
+			r#"exec_with_input(bytes([97, 255]), "cat")"#,
+			"bytes([97, 255])",
+		);
+		// More than a pipe buffer, both read back and left unread
+		assert_evals_to(
+			r#"let big = repeat("x", 1000000); exec_with_input(big, "cat") == big"#,
+			"true",
+		);
+		assert_evals_to(r#"exec_with_input(repeat("x", 1000000), "true")"#, r#""""#);
+
+		assert!(eval_err(r#"exec_with_input("x", "sh", "-c", "exit 2")"#)
+			.ends_with("in exec_with_input: command failed (exit status: 2)"));
+		assert!(eval_err(r#"exec_with_input("x")"#).contains("argument"));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn exec_capture_timing() {
+		let (mut vm, _) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"let timed = exec_capture(["timed"], "sh", "-c", "echo hi; echo err >&2; sleep 0.05");
+			let plain = exec_capture([], "true");"#,
+		)
+		.unwrap();
+
+		let Some(VmVariant::Map(timed)) = vm.get_global("timed") else {
+			panic!("exec_capture should return a map");
+		};
+		assert!(matches!(timed["duration_ms"], VmVariant::Integer(ms) if ms >= 50));
+		assert_eq!(timed["stdout"].to_string(), "hi\n");
+		assert_eq!(timed["stderr"].to_string(), "err\n");
+		assert_eq!(timed["status"].to_string(), "0");
+
+		// Timing is opt-in
+		assert_eq!(
+			vm.get_global("plain").unwrap().to_string(),
+			"{status: 0, stderr: , stdout: }"
+		);
+	}
 }
@@ -0,0 +1,246 @@
+use std::{
+	collections::{BTreeSet, HashMap},
+	fmt::Display,
+};
+
+use crate::{
+	common::Location,
+	parser::types::{Expr, LocatedType, MatchPattern, ParsedHighLevel, StatementBlock},
+};
+
+use super::Vm;
+
+/// A call to a user function with an argument count that none of its declarations take.
+#[derive(Debug)]
+pub struct ArityMismatch {
+	pub location: Location,
+	pub func_name: String,
+	pub got: usize,
+	pub arities: BTreeSet<usize>,
+}
+
+impl Display for ArityMismatch {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}: {} called with {} arguments, but declared with {:?}",
+			self.location, self.func_name, self.got, self.arities
+		)
+	}
+}
+
+impl Vm {
+	/// Checks the arity of every call to a function declared in `packages` without running
+	/// anything. Scopes are ignored: a call only needs to match one declaration with that name.
+	/// Builtins and functions that aren't declared are left for the runtime to resolve.
+	pub fn check_arities(&self, packages: &[LocatedType<ParsedHighLevel>]) -> Vec<ArityMismatch> {
+		let mut functions = ProgramFunctions::default();
+
+		for package in packages {
+			functions.visit_statement(package);
+		}
+
+		let mut mismatches = Vec::new();
+
+		for (location, func_name, got) in functions.calls {
+			// Builtins are looked up first when calling, so they hide user functions
+			if self.builtins.contains_key(&func_name) {
+				continue;
+			}
+
+			let Some(arities) = functions.declared.get(&func_name) else {
+				continue;
+			};
+
+			if !arities.contains(&got) {
+				mismatches.push(ArityMismatch {
+					location,
+					func_name,
+					got,
+					arities: arities.clone(),
+				});
+			}
+		}
+
+		mismatches
+	}
+}
+
+// Function declarations and calls of a whole program. Calls are located by the statement (or
+// block tail) they are part of, as expressions have no location of their own.
+#[derive(Default)]
+struct ProgramFunctions {
+	declared: HashMap<String, BTreeSet<usize>>,
+	calls: Vec<(Location, String, usize)>,
+}
+
+impl ProgramFunctions {
+	fn visit_block(&mut self, block: &StatementBlock) {
+		for statement in &block.statements {
+			self.visit_statement(statement);
+		}
+
+		if let Some(tail) = block.tail.as_ref() {
+			self.visit_expr(&tail.inner, &tail.location);
+		}
+	}
+
+	fn visit_statement(&mut self, statement: &LocatedType<ParsedHighLevel>) {
+		let location = &statement.location;
+
+		match &statement.inner {
+			ParsedHighLevel::Noop | ParsedHighLevel::Alias(_) => (),
+			ParsedHighLevel::VarDecl(assign)
+			| ParsedHighLevel::ConstDecl(assign)
+			| ParsedHighLevel::VarSet(assign) => self.visit_expr(&assign.val, location),
+			ParsedHighLevel::FuncDecl(func_decl) => {
+				self.declared
+					.entry(func_decl.name.clone())
+					.or_default()
+					.insert(func_decl.args.len());
+
+				self.visit_block(&func_decl.block);
+			}
+			ParsedHighLevel::FuncCall(call) => {
+				self.calls
+					.push((location.clone(), call.name.clone(), call.args.len()));
+
+				for arg in &call.args {
+					self.visit_expr(arg, location);
+				}
+			}
+			ParsedHighLevel::If(if_statement) => {
+				self.visit_expr(&if_statement.val, location);
+				self.visit_block(&if_statement.block);
+
+				if let Some(else_block) = if_statement.else_block.as_ref() {
+					self.visit_block(else_block);
+				}
+			}
+			ParsedHighLevel::Match(match_statement) => {
+				self.visit_expr(&match_statement.val, location);
+
+				for arm in &match_statement.arms {
+					match &arm.pattern {
+						MatchPattern::Wildcard | MatchPattern::Binding(_) => (),
+						MatchPattern::Literal(literal) => self.visit_expr(literal, location),
+						MatchPattern::Range { start, end, .. } => {
+							self.visit_expr(start, location);
+							self.visit_expr(end, location);
+						}
+					}
+
+					if let Some(guard) = arm.guard.as_ref() {
+						self.visit_expr(guard, location);
+					}
+
+					self.visit_block(&arm.block);
+				}
+			}
+			ParsedHighLevel::Loop(block) | ParsedHighLevel::Block(block) => self.visit_block(block),
+			ParsedHighLevel::Break(value) => {
+				if let Some(value) = value.as_ref() {
+					self.visit_expr(value, location);
+				}
+			}
+		}
+	}
+
+	fn visit_expr(&mut self, expr: &Expr, location: &Location) {
+		match expr {
+			Expr::IntLiteral(_)
+			| Expr::StringLiteral(_)
+			| Expr::BoolLiteral(_)
+			| Expr::Identifier(_) => (),
+			Expr::FuncCall(call) => {
+				self.calls
+					.push((location.clone(), call.name.clone(), call.args.len()));
+
+				for arg in &call.args {
+					self.visit_expr(arg, location);
+				}
+			}
+			Expr::Array(array) => {
+				for elem in &array.args {
+					self.visit_expr(elem, location);
+				}
+			}
+			Expr::Map(map) => {
+				for (key, value) in &map.entries {
+					self.visit_expr(key, location);
+					self.visit_expr(value, location);
+				}
+			}
+			Expr::Index(index) => {
+				self.visit_expr(&index.target, location);
+				self.visit_expr(&index.index, location);
+			}
+			Expr::Member(member) => self.visit_expr(&member.target, location),
+			Expr::Comparison(comparison) => {
+				self.visit_expr(&comparison.lhs, location);
+				self.visit_expr(&comparison.rhs, location);
+			}
+			Expr::Assign(assign) => self.visit_expr(&assign.val, location),
+			Expr::Block(block) | Expr::Loop(block) => self.visit_block(block),
+			Expr::If(if_statement) => {
+				self.visit_expr(&if_statement.val, location);
+				self.visit_block(&if_statement.block);
+
+				if let Some(else_block) = if_statement.else_block.as_ref() {
+					self.visit_block(else_block);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use crate::{
+		lexer::Lexer,
+		parser::Parser,
+		vm::{testing::test_vm, Vm},
+	};
+
+	fn mismatches(vm: &Vm, source: &str) -> Vec<String> {
+		let packages = Parser::new(
+			Lexer::new(Cursor::new(source.to_string()), "test".into()),
+			"test".into(),
+		)
+		.parse_all()
+		.expect("test script should parse");
+
+		vm.check_arities(&packages)
+			.iter()
+			.map(ToString::to_string)
+			.collect()
+	}
+
+	#[test]
+	fn reports_arity_mismatches() {
+		let (vm, _) = test_vm();
+
+		assert_eq!(
+			mismatches(
+				&vm,
+				concat!(
+					"fn f(a) { a }\nfn f(a, b) { a }\n",
+					"f(1);\nlet x = [f()];\nif true {\n\tf(1, 2, 3);\n}",
+				)
+			),
+			[
+				"test:4:1: f called with 0 arguments, but declared with {1, 2}",
+				"test:6:2: f called with 3 arguments, but declared with {1, 2}",
+			]
+		);
+
+		// Calls before the declaration count, builtins and unknown functions are skipped
+		assert_eq!(
+			mismatches(&vm, "g(1);\nfn g() { 1 }\nprintln();\nmissing(1, 2);"),
+			["test:1:1: g called with 1 arguments, but declared with {0}"]
+		);
+		assert!(mismatches(&vm, "fn h(a) { h(a) }\nh(1);").is_empty());
+	}
+}
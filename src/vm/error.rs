@@ -1,15 +1,19 @@
-use std::fmt::Display;
+use std::{fmt::Display, process::ExitStatus, time::Duration};
 
 use thiserror::Error;
 
 use crate::common::Location;
 
+use super::variant::VmVariant;
+
 pub type VmResult<T> = std::result::Result<T, VmError>;
 
 #[derive(Debug)]
 pub struct VmError {
 	err_type: VmErrorType,
 	context: Box<VmErrorContext>,
+	// User functions the error unwound through, innermost first
+	call_stack: Vec<(String, Location)>,
 }
 
 impl Display for VmError {
@@ -20,6 +24,7 @@ impl Display for VmError {
 				location.file().fmt(f)?;
 				':'.fmt(f)?;
 				location.line().fmt(f)?;
+				':'.fmt(f)?;
 				location.column().fmt(f)?;
 			}
 		}
@@ -43,7 +48,13 @@ impl Display for VmError {
 			": ".fmt(f)?;
 		}
 
-		self.err_type.fmt(f)
+		self.err_type.fmt(f)?;
+
+		for (func_name, location) in &self.call_stack {
+			write!(f, "\n  in {func_name} (called at {location})")?;
+		}
+
+		Ok(())
 	}
 }
 
@@ -52,9 +63,17 @@ impl VmError {
 		Self {
 			err_type,
 			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 
+	/// Records that the error unwound through a call to `func_name` made at `location`.
+	pub fn with_call_frame(mut self, func_name: String, location: Location) -> Self {
+		self.call_stack.push((func_name, location));
+
+		self
+	}
+
 	pub fn with_context_internal(mut self) -> Self {
 		self.context = Box::from(VmErrorContext::Internal);
 
@@ -96,6 +115,15 @@ impl VmError {
 		Self {
 			err_type: VmErrorType::UnknownIdentifier(name),
 			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn unknown_type(name: String) -> Self {
+		Self {
+			err_type: VmErrorType::UnknownType(name),
+			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 
@@ -103,6 +131,7 @@ impl VmError {
 		Self {
 			err_type: VmErrorType::FuncNameDuplicate(name),
 			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 
@@ -110,6 +139,15 @@ impl VmError {
 		Self {
 			err_type: VmErrorType::VarNameDuplicate(name),
 			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn assign_to_const(name: String) -> Self {
+		Self {
+			err_type: VmErrorType::AssignToConst(name),
+			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 
@@ -129,6 +167,15 @@ impl VmError {
 		Self {
 			err_type,
 			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn no_matching_overload(got: usize, arities: Vec<usize>) -> Self {
+		Self {
+			err_type: VmErrorType::NoMatchingOverload { got, arities },
+			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 
@@ -136,6 +183,197 @@ impl VmError {
 		Self {
 			err_type: VmErrorType::InvalidValueType { expected, got },
 			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn invalid_comparison(lhs: String, rhs: String) -> Self {
+		Self {
+			err_type: VmErrorType::InvalidComparison { lhs, rhs },
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn empty_array() -> Self {
+		Self {
+			err_type: VmErrorType::EmptyArray,
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn integer_overflow() -> Self {
+		Self {
+			err_type: VmErrorType::IntegerOverflow,
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn invalid_value(reason: String) -> Self {
+		Self {
+			err_type: VmErrorType::InvalidValue(reason),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn invalid_integer(string: String, radix: u32) -> Self {
+		Self {
+			err_type: VmErrorType::InvalidInteger { string, radix },
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn json_error(reason: String, offset: usize) -> Self {
+		Self {
+			err_type: VmErrorType::JsonError { reason, offset },
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn index_out_of_bounds(index: i64, len: usize) -> Self {
+		Self {
+			err_type: VmErrorType::IndexOutOfBounds { index, len },
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn unknown_key(key: String) -> Self {
+		Self {
+			err_type: VmErrorType::UnknownKey(key),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn no_input() -> Self {
+		Self {
+			err_type: VmErrorType::NoInput,
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn io(err: std::io::Error) -> Self {
+		Self {
+			err_type: VmErrorType::Io(err),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn command_failed(status: ExitStatus) -> Self {
+		Self {
+			err_type: VmErrorType::CommandFailed(status),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn assertion_failed(reason: String) -> Self {
+		Self {
+			err_type: VmErrorType::AssertionFailed(reason),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn exit(code: i32) -> Self {
+		Self {
+			err_type: VmErrorType::Exit(code),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn step_limit_exceeded(limit: u64) -> Self {
+		Self {
+			err_type: VmErrorType::StepLimitExceeded(limit),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn timeout(timeout: Duration) -> Self {
+		Self {
+			err_type: VmErrorType::Timeout(timeout),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	pub fn break_loop(value: VmVariant) -> Self {
+		Self {
+			err_type: VmErrorType::Break(Box::new(value)),
+			context: Box::default(),
+			call_stack: Vec::new(),
+		}
+	}
+
+	/// Stops the unwinding started by `break`, giving the value the loop evaluates to. Any other
+	/// error keeps going.
+	pub fn into_break_value(self) -> VmResult<VmVariant> {
+		match self.err_type {
+			VmErrorType::Break(value) => Ok(*value),
+			_ => Err(self),
+		}
+	}
+
+	/// A `break` can't reach a loop outside of the function it was used in.
+	pub fn escape_function(self) -> Self {
+		match self.err_type {
+			VmErrorType::Break(_) => Self {
+				err_type: VmErrorType::BreakOutsideLoop,
+				..self
+			},
+			_ => self,
+		}
+	}
+
+	/// Returns the requested exit code if this "error" comes from the `exit` builtin.
+	pub fn exit_code(&self) -> Option<i32> {
+		match self.err_type {
+			VmErrorType::Exit(code) => Some(code),
+			_ => None,
+		}
+	}
+
+	/// Tells embedders (and the REPL) what to make of the error.
+	pub fn category(&self) -> VmErrorCategory {
+		match self.err_type {
+			VmErrorType::Exit(_) => VmErrorCategory::Exit,
+			VmErrorType::NoInput | VmErrorType::Io(_) | VmErrorType::CommandFailed(_) => {
+				VmErrorCategory::Host
+			}
+			VmErrorType::UnknownIdentifier(_)
+			| VmErrorType::UnknownType(_)
+			| VmErrorType::FuncNameDuplicate(_)
+			| VmErrorType::VarNameDuplicate(_)
+			| VmErrorType::AssignToConst(_)
+			| VmErrorType::NotEnoughArg { .. }
+			| VmErrorType::TooMuchArgs { .. }
+			| VmErrorType::NoMatchingOverload { .. }
+			| VmErrorType::InvalidValueType { .. }
+			| VmErrorType::InvalidComparison { .. }
+			| VmErrorType::EmptyArray
+			| VmErrorType::IntegerOverflow
+			| VmErrorType::InvalidValue(_)
+			| VmErrorType::InvalidInteger { .. }
+			| VmErrorType::JsonError { .. }
+			| VmErrorType::IndexOutOfBounds { .. }
+			| VmErrorType::UnknownKey(_)
+			| VmErrorType::AssertionFailed(_)
+			| VmErrorType::Break(_)
+			| VmErrorType::BreakOutsideLoop
+			| VmErrorType::StepLimitExceeded(_)
+			| VmErrorType::Timeout(_)
+			| VmErrorType::InvalidString { .. }
+			| VmErrorType::InvalidEscape { .. } => VmErrorCategory::Script,
 		}
 	}
 
@@ -146,6 +384,7 @@ impl VmError {
 				invalid_char_idx,
 			},
 			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 
@@ -156,10 +395,21 @@ impl VmError {
 				invalid_escape_idx,
 			},
 			context: Box::default(),
+			call_stack: Vec::new(),
 		}
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmErrorCategory {
+	/* The script asked to stop with `exit`, which isn't a failure */
+	Exit,
+	/* The script did something wrong; the VM is left in a usable state */
+	Script,
+	/* The host environment failed (I/O, missing input stream) */
+	Host,
+}
+
 #[derive(Debug, Default)]
 pub enum VmErrorContext {
 	/* No context, error generated by VM internals */
@@ -181,16 +431,58 @@ pub enum VmErrorContext {
 pub enum VmErrorType {
 	#[error("unknown identifier: {0}")]
 	UnknownIdentifier(String),
+	#[error("unknown type: {0}")]
+	UnknownType(String),
 	#[error("duplicate function: {0}")]
 	FuncNameDuplicate(String),
 	#[error("duplicate variable: {0}")]
 	VarNameDuplicate(String),
+	#[error("cannot assign to constant: {0}")]
+	AssignToConst(String),
 	#[error("not enough argument (expected {expected}, got {got})")]
 	NotEnoughArg { expected: usize, got: usize },
 	#[error("too many arguments (expected {expected}, got {got})")]
 	TooMuchArgs { expected: usize, got: usize },
+	#[error("no overload takes {got} arguments (declared arities: {arities:?})")]
+	NoMatchingOverload { got: usize, arities: Vec<usize> },
 	#[error("unexpected type (expected {expected}, got {got})")]
 	InvalidValueType { expected: String, got: String },
+	#[error("cannot compare {lhs} with {rhs}")]
+	InvalidComparison { lhs: String, rhs: String },
+	#[error("empty array")]
+	EmptyArray,
+	#[error("integer overflow")]
+	IntegerOverflow,
+	#[error("invalid value: {0}")]
+	InvalidValue(String),
+	#[error("invalid integer in base {radix}: {string:?}")]
+	InvalidInteger { string: String, radix: u32 },
+	#[error("invalid JSON at offset {offset}: {reason}")]
+	JsonError { reason: String, offset: usize },
+	#[error("index out of bounds (index {index}, length {len})")]
+	IndexOutOfBounds { index: i64, len: usize },
+	#[error("unknown key: {0}")]
+	UnknownKey(String),
+	#[error("assertion failed: {0}")]
+	AssertionFailed(String),
+	#[error("step limit exceeded ({0} steps)")]
+	StepLimitExceeded(u64),
+	#[error("timed out after {0:?}")]
+	Timeout(Duration),
+	#[error("no input stream available")]
+	NoInput,
+	#[error("I/O error: {0}")]
+	Io(std::io::Error),
+	#[error("command failed ({0})")]
+	CommandFailed(ExitStatus),
+	// Not an actual error either, unless no loop catches it
+	#[error("break outside of a loop")]
+	Break(Box<VmVariant>),
+	#[error("break outside of a loop")]
+	BreakOutsideLoop,
+	// Not an actual error: unwinds the whole script when `exit` is called
+	#[error("exit requested with code {0}")]
+	Exit(i32),
 	#[error("invalid string value: invalid char at {invalid_char_idx}")]
 	InvalidString {
 		raw_string: String,
@@ -233,3 +525,86 @@ impl<T> VmResultExt for VmResult<T> {
 		self.map_err(|v| v.with_context_func_arg(location, func_name, arg_name))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::{process::ExitStatus, time::Duration};
+
+	use super::{VmError, VmErrorCategory, VmErrorType};
+	use crate::vm::variant::VmVariant;
+
+	#[test]
+	fn categories() {
+		let category = |err_type| VmError::new(err_type).category();
+
+		assert_eq!(category(VmErrorType::Exit(0)), VmErrorCategory::Exit);
+
+		let host = [
+			VmErrorType::NoInput,
+			VmErrorType::Io(std::io::ErrorKind::NotFound.into()),
+			VmErrorType::CommandFailed(ExitStatus::default()),
+		];
+
+		for err_type in host {
+			assert_eq!(category(err_type), VmErrorCategory::Host);
+		}
+
+		let script = [
+			VmErrorType::UnknownIdentifier("x".into()),
+			VmErrorType::UnknownType("T".into()),
+			VmErrorType::FuncNameDuplicate("f".into()),
+			VmErrorType::VarNameDuplicate("x".into()),
+			VmErrorType::AssignToConst("x".into()),
+			VmErrorType::NotEnoughArg {
+				expected: 1,
+				got: 0,
+			},
+			VmErrorType::TooMuchArgs {
+				expected: 0,
+				got: 1,
+			},
+			VmErrorType::NoMatchingOverload {
+				got: 3,
+				arities: vec![1, 2],
+			},
+			VmErrorType::InvalidValueType {
+				expected: "Integer".into(),
+				got: "String".into(),
+			},
+			VmErrorType::InvalidComparison {
+				lhs: "Integer".into(),
+				rhs: "String".into(),
+			},
+			VmErrorType::EmptyArray,
+			VmErrorType::IntegerOverflow,
+			VmErrorType::InvalidValue("x".into()),
+			VmErrorType::InvalidInteger {
+				string: "x".into(),
+				radix: 10,
+			},
+			VmErrorType::JsonError {
+				reason: "x".into(),
+				offset: 0,
+			},
+			VmErrorType::IndexOutOfBounds { index: 1, len: 0 },
+			VmErrorType::UnknownKey("k".into()),
+			VmErrorType::AssertionFailed("x".into()),
+			VmErrorType::Break(Box::new(VmVariant::Unit)),
+			VmErrorType::BreakOutsideLoop,
+			VmErrorType::StepLimitExceeded(1),
+			VmErrorType::Timeout(Duration::from_secs(1)),
+			VmErrorType::InvalidString {
+				raw_string: "x".into(),
+				invalid_char_idx: 0,
+			},
+			VmErrorType::InvalidEscape {
+				raw_string: "x".into(),
+				invalid_escape_idx: 0,
+			},
+		];
+
+		for err_type in script {
+			assert_eq!(category(err_type), VmErrorCategory::Script);
+		}
+	}
+}
@@ -0,0 +1,417 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use super::{
+	error::{VmError, VmResult},
+	variant::VmVariant,
+};
+
+pub fn to_json(value: &VmVariant) -> String {
+	let mut out = String::new();
+
+	write_json(value, &mut out);
+
+	out
+}
+
+fn write_json(value: &VmVariant, out: &mut String) {
+	match value {
+		VmVariant::Unit => out.push_str("null"),
+		VmVariant::Bool(v) => out.push_str(if *v { "true" } else { "false" }),
+		VmVariant::Integer(v) => {
+			let _ = write!(out, "{v}");
+		}
+		VmVariant::String(v) => write_json_string(v, out),
+		VmVariant::Array(array) => {
+			out.push('[');
+
+			for (idx, elem) in array.iter().enumerate() {
+				if idx != 0 {
+					out.push(',');
+				}
+
+				write_json(elem, out);
+			}
+
+			out.push(']');
+		}
+		VmVariant::Map(map) => {
+			out.push('{');
+
+			for (idx, (key, value)) in map.iter().enumerate() {
+				if idx != 0 {
+					out.push(',');
+				}
+
+				write_json_string(key, out);
+				out.push(':');
+				write_json(value, out);
+			}
+
+			out.push('}');
+		}
+		// JSON has no byte strings, an array of integers is the closest
+		VmVariant::Bytes(bytes) => {
+			out.push('[');
+
+			for (idx, byte) in bytes.iter().enumerate() {
+				if idx != 0 {
+					out.push(',');
+				}
+
+				let _ = write!(out, "{byte}");
+			}
+
+			out.push(']');
+		}
+		VmVariant::Ref(v) => write_json(v, out),
+	}
+}
+
+fn write_json_string(value: &str, out: &mut String) {
+	out.push('"');
+
+	for c in value.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			'\u{08}' => out.push_str("\\b"),
+			'\u{0C}' => out.push_str("\\f"),
+			c if c.is_control() => {
+				let _ = write!(out, "\\u{:04x}", c as u32);
+			}
+			c => out.push(c),
+		}
+	}
+
+	out.push('"');
+}
+
+// Arrays and objects are parsed recursively, this keeps hostile input from overflowing the stack
+const MAX_DEPTH: usize = 512;
+
+pub fn from_json(source: &str) -> VmResult<VmVariant> {
+	let mut parser = JsonParser {
+		source,
+		pos: 0,
+		depth: 0,
+	};
+
+	let value = parser.parse_value()?;
+
+	parser.skip_whitespace();
+
+	if parser.pos != source.len() {
+		return Err(parser.error("trailing characters"));
+	}
+
+	Ok(value)
+}
+
+struct JsonParser<'a> {
+	source: &'a str,
+	pos: usize,
+	depth: usize,
+}
+
+impl JsonParser<'_> {
+	fn parse_value(&mut self) -> VmResult<VmVariant> {
+		self.skip_whitespace();
+
+		match self.peek() {
+			Some('n') => self.parse_keyword("null", VmVariant::Unit),
+			Some('t') => self.parse_keyword("true", VmVariant::Bool(true)),
+			Some('f') => self.parse_keyword("false", VmVariant::Bool(false)),
+			Some('"') => Ok(VmVariant::String(self.parse_string()?)),
+			Some('[') => self.nested(Self::parse_array),
+			Some('{') => self.nested(Self::parse_object),
+			Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+			Some(c) => Err(self.error(&format!("unexpected character {c:?}"))),
+			None => Err(self.error("unexpected end of input")),
+		}
+	}
+
+	fn nested(&mut self, parse: fn(&mut Self) -> VmResult<VmVariant>) -> VmResult<VmVariant> {
+		if self.depth == MAX_DEPTH {
+			return Err(self.error("nesting too deep"));
+		}
+
+		self.depth += 1;
+		let res = parse(self);
+		self.depth -= 1;
+
+		res
+	}
+
+	fn parse_keyword(&mut self, keyword: &str, value: VmVariant) -> VmResult<VmVariant> {
+		if !self.source[self.pos..].starts_with(keyword) {
+			return Err(self.error(&format!("expected {keyword}")));
+		}
+
+		self.pos += keyword.len();
+
+		Ok(value)
+	}
+
+	fn parse_number(&mut self) -> VmResult<VmVariant> {
+		let start = self.pos;
+
+		if self.peek() == Some('-') {
+			self.pos += 1;
+		}
+
+		while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+			self.pos += 1;
+		}
+
+		let digits = &self.source[start..self.pos];
+
+		if let Some('.' | 'e' | 'E') = self.peek() {
+			return Err(self.error("only integer numbers are supported"));
+		}
+
+		if digits.trim_start_matches('-').len() > 1
+			&& digits.trim_start_matches('-').starts_with('0')
+		{
+			return Err(error_at("leading zeros are not allowed", start));
+		}
+
+		digits
+			.parse::<i64>()
+			.map(VmVariant::Integer)
+			.map_err(|err| error_at(&err.to_string(), start))
+	}
+
+	fn parse_string(&mut self) -> VmResult<String> {
+		// Opening quote
+		self.pos += 1;
+
+		let mut res = String::new();
+
+		loop {
+			let Some(c) = self.next() else {
+				return Err(self.error("unterminated string"));
+			};
+
+			match c {
+				'"' => return Ok(res),
+				'\\' => {
+					let escape = match self.next() {
+						Some('"') => '"',
+						Some('\\') => '\\',
+						Some('/') => '/',
+						Some('b') => '\u{08}',
+						Some('f') => '\u{0C}',
+						Some('n') => '\n',
+						Some('r') => '\r',
+						Some('t') => '\t',
+						Some('u') => self.parse_unicode_escape()?,
+						_ => return Err(self.error("invalid escape sequence")),
+					};
+
+					res.push(escape);
+				}
+				c if c.is_control() => return Err(self.error("unescaped control character")),
+				c => res.push(c),
+			}
+		}
+	}
+
+	fn parse_unicode_escape(&mut self) -> VmResult<char> {
+		let high = self.parse_hex4()?;
+
+		let code_point = if (0xD800..0xDC00).contains(&high) {
+			if !self.source[self.pos..].starts_with("\\u") {
+				return Err(self.error("unpaired surrogate"));
+			}
+
+			self.pos += 2;
+
+			let low = self.parse_hex4()?;
+
+			if !(0xDC00..0xE000).contains(&low) {
+				return Err(self.error("invalid low surrogate"));
+			}
+
+			0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+		} else {
+			high
+		};
+
+		char::from_u32(code_point).ok_or_else(|| self.error("invalid code point"))
+	}
+
+	fn parse_hex4(&mut self) -> VmResult<u32> {
+		let Some(hex) = self.source.get(self.pos..(self.pos + 4)) else {
+			return Err(self.error("truncated unicode escape"));
+		};
+
+		// from_str_radix would also take a leading sign
+		if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+			return Err(self.error("invalid unicode escape"));
+		}
+
+		let value = u32::from_str_radix(hex, 16).unwrap();
+
+		self.pos += 4;
+
+		Ok(value)
+	}
+
+	fn parse_array(&mut self) -> VmResult<VmVariant> {
+		// Opening bracket
+		self.pos += 1;
+
+		let mut elems = Vec::new();
+
+		self.skip_whitespace();
+
+		if self.peek() == Some(']') {
+			self.pos += 1;
+			return Ok(VmVariant::Array(elems));
+		}
+
+		loop {
+			elems.push(self.parse_value()?);
+
+			self.skip_whitespace();
+
+			match self.next() {
+				Some(',') => (),
+				Some(']') => return Ok(VmVariant::Array(elems)),
+				_ => return Err(self.error("expected , or ]")),
+			}
+		}
+	}
+
+	// Duplicate keys are allowed, the last one wins
+	fn parse_object(&mut self) -> VmResult<VmVariant> {
+		// Opening brace
+		self.pos += 1;
+
+		let mut entries = BTreeMap::new();
+
+		self.skip_whitespace();
+
+		if self.peek() == Some('}') {
+			self.pos += 1;
+			return Ok(VmVariant::Map(entries));
+		}
+
+		loop {
+			self.skip_whitespace();
+
+			if self.peek() != Some('"') {
+				return Err(self.error("expected string key"));
+			}
+
+			let key = self.parse_string()?;
+
+			self.skip_whitespace();
+
+			if self.next() != Some(':') {
+				return Err(self.error("expected :"));
+			}
+
+			entries.insert(key, self.parse_value()?);
+
+			self.skip_whitespace();
+
+			match self.next() {
+				Some(',') => (),
+				Some('}') => return Ok(VmVariant::Map(entries)),
+				_ => return Err(self.error("expected , or }")),
+			}
+		}
+	}
+
+	fn skip_whitespace(&mut self) {
+		while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+			self.pos += 1;
+		}
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.source[self.pos..].chars().next()
+	}
+
+	fn next(&mut self) -> Option<char> {
+		let c = self.peek()?;
+
+		self.pos += c.len_utf8();
+
+		Some(c)
+	}
+
+	fn error(&self, reason: &str) -> VmError {
+		error_at(reason, self.pos)
+	}
+}
+
+fn error_at(reason: &str, offset: usize) -> VmError {
+	VmError::json_error(reason.to_string(), offset)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{from_json, to_json, MAX_DEPTH};
+	use crate::vm::testing::eval;
+
+	fn json_of(source: &str) -> String {
+		to_json(&eval(source).expect("test value should evaluate"))
+	}
+
+	#[test]
+	fn nested_to_json() {
+		assert_eq!(json_of("[]"), "[]");
+		assert_eq!(json_of("{}"), "{}");
+		assert_eq!(
+			json_of(r#"{ "b": [1, [true, false]], "a": { "x": "y" } }"#),
+			r#"{"a":{"x":"y"},"b":[1,[true,false]]}"#
+		);
+		assert_eq!(json_of("[[[]], typename(1)]"), r#"[[[]],"Integer"]"#);
+		assert_eq!(json_of(r#""tab\t\"quote\"""#), r#""tab\t\"quote\"""#);
+	}
+
+	#[test]
+	fn round_trip() {
+		let source = r#"{"a":[1,-2,{"b":null}],"c":"é\n\u0001","d":true}"#;
+		let value = from_json(source).unwrap();
+
+		assert_eq!(
+			to_json(&value),
+			r#"{"a":[1,-2,{"b":null}],"c":"é\n\u0001","d":true}"#
+		);
+		assert_eq!(
+			to_json(&from_json(&to_json(&value)).unwrap()),
+			to_json(&value)
+		);
+		assert_eq!(from_json(r#""😀""#).unwrap().to_string(), "😀");
+	}
+
+	#[test]
+	fn from_json_errors() {
+		let err = |source: &str| from_json(source).unwrap_err().to_string();
+
+		assert!(err("[1,]").contains("unexpected character"));
+		assert!(err("01").contains("leading zeros"));
+		assert!(err("1.5").contains("only integer"));
+		assert!(err(r#""\u+041""#).contains("invalid unicode escape"));
+		assert!(err(r#""\ud83d""#).contains("unpaired surrogate"));
+		assert!(err("[1] 2").contains("trailing characters"));
+	}
+
+	#[test]
+	fn from_json_depth_limit() {
+		let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+
+		assert!(from_json(&nested(MAX_DEPTH)).is_ok());
+		assert!(from_json(&nested(MAX_DEPTH + 1))
+			.unwrap_err()
+			.to_string()
+			.contains("nesting too deep"));
+		assert!(from_json(&"[".repeat(100_000)).is_err());
+	}
+}
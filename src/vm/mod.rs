@@ -1,22 +1,36 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+	cmp::Ordering,
+	collections::{BTreeMap, BTreeSet, HashMap},
+	fmt::Display,
+	io::{Read, Write},
+	process::Child,
+	rc::Rc,
+	time::{Duration, Instant},
+};
 
 use crate::{
 	common::Location,
 	parser::types::{
-		ArrayExpr, Expr, FuncCallExpr, FuncDecl, IfStatement, LocatedType, ParsedHighLevel,
-		VarAssign,
+		ArrayExpr, AssignExpr, ComparisonExpr, ComparisonOp, Expr, FuncCallExpr, FuncDecl,
+		IfStatement, IndexExpr, LocatedType, MapExpr, MatchArm, MatchPattern, MatchStatement,
+		MemberExpr, ParsedHighLevel, StatementBlock, VarAssign,
 	},
 };
 
 use self::{
 	error::{VmError, VmResultExt},
+	types::{VmTypable, VmType},
 	variant::{IntoVariant, VmVariant},
 };
 
 mod builtins;
-mod error;
-mod types;
-mod variant;
+mod check;
+pub mod error;
+mod json;
+#[cfg(test)]
+mod testing;
+pub mod types;
+pub mod variant;
 
 use error::VmResult;
 
@@ -25,15 +39,41 @@ type Builtin = fn(&mut Vm, String, Vec<VmVariant>) -> VmResult<VmVariant>;
 type VmFuncVarAssign<T = VmVariant> = fn(&mut Vm, String, T) -> VmResult<()>;
 
 struct FunctionData {
-	packages: Vec<LocatedType<ParsedHighLevel>>,
+	block: StatementBlock,
 	args: Vec<String>,
-	// return_type: VmType,
+	arg_types: Vec<VmType>,
+	return_type: VmType,
+}
+
+struct StoredValue {
+	value: VmVariant,
+	constant: bool,
+}
+
+impl StoredValue {
+	fn variable(value: VmVariant) -> Self {
+		Self {
+			value,
+			constant: false,
+		}
+	}
+
+	fn constant(value: VmVariant) -> Self {
+		Self {
+			value,
+			constant: true,
+		}
+	}
 }
 
 struct Scope {
-	variables: HashMap<String, VmVariant>,
-	functions: HashMap<String, Rc<FunctionData>>,
-	caller: Location,
+	variables: HashMap<String, StoredValue>,
+	// Overloads of each function, by arity
+	functions: HashMap<String, BTreeMap<usize, Rc<FunctionData>>>,
+	// Where the function owning this scope was called from, `None` outside of functions
+	caller: Option<Location>,
+	// Enclosing scope of a block, which stays visible from inside it
+	parent: Option<Box<Scope>>,
 }
 
 impl Scope {
@@ -41,7 +81,8 @@ impl Scope {
 		Scope {
 			variables: HashMap::new(),
 			functions: HashMap::new(),
-			caller: Location::new_z(0, 0, "_vm".into()),
+			caller: None,
+			parent: None,
 		}
 	}
 
@@ -49,7 +90,62 @@ impl Scope {
 		Scope {
 			variables: HashMap::new(),
 			functions: HashMap::new(),
-			caller,
+			caller: Some(caller),
+			parent: None,
+		}
+	}
+
+	fn new_block(parent: Option<Scope>) -> Self {
+		let mut scope = Scope::new();
+
+		scope.caller = parent.as_ref().and_then(|parent| parent.caller.clone());
+
+		scope.parent = parent.map(Box::new);
+		scope
+	}
+
+	fn find_variable(&self, var_name: &str) -> Option<&StoredValue> {
+		match self.variables.get(var_name) {
+			Some(stored) => Some(stored),
+			None => self.parent.as_deref()?.find_variable(var_name),
+		}
+	}
+
+	fn find_variable_mut(&mut self, var_name: &str) -> Option<&mut StoredValue> {
+		if self.variables.contains_key(var_name) {
+			return self.variables.get_mut(var_name);
+		}
+
+		self.parent.as_deref_mut()?.find_variable_mut(var_name)
+	}
+
+	fn find_function(&self, func_name: &str, arity: usize) -> Option<&Rc<FunctionData>> {
+		match self
+			.functions
+			.get(func_name)
+			.and_then(|overloads| overloads.get(&arity))
+		{
+			Some(func) => Some(func),
+			None => self.parent.as_deref()?.find_function(func_name, arity),
+		}
+	}
+
+	fn has_function(&self, func_name: &str) -> bool {
+		self.functions.contains_key(func_name)
+			|| self
+				.parent
+				.as_deref()
+				.is_some_and(|parent| parent.has_function(func_name))
+	}
+
+	// Every arity `func_name` is declared with, in this scope and the enclosing ones
+	fn collect_arities(&self, func_name: &str, arities: &mut BTreeSet<usize>) {
+		if let Some(overloads) = self.functions.get(func_name) {
+			arities.extend(overloads.keys());
+		}
+
+		if let Some(parent) = self.parent.as_deref() {
+			parent.collect_arities(func_name, arities);
 		}
 	}
 }
@@ -58,9 +154,44 @@ pub struct Vm {
 	global_scope: Scope,
 	stack_scope: Option<Scope>,
 	builtins: HashMap<String, Builtin>,
+	// Alternative names of functions, resolved when calling
+	aliases: HashMap<String, String>,
 	allow_var_shadowing: bool,
 	allow_implicit_var: bool,
 	root_package_location: Location,
+	// Read by `read_all`; unset when the script itself comes from stdin
+	input: Option<Box<dyn Read>>,
+	// Written to by `println`
+	output: Box<dyn Write>,
+	// Diagnostics, see `report_error`
+	error_output: Box<dyn Write>,
+	// Command-line arguments given to the script, returned by `args`
+	args: Vec<String>,
+	// Processes started by `spawn` and not yet waited for, by PID
+	children: HashMap<u32, Child>,
+	// xorshift state for `random`, never zero
+	rng_state: u64,
+	// Statements, expressions and blocks evaluated so far, see `set_step_limit`
+	steps: u64,
+	step_limit: Option<u64>,
+	// Set by `set_timeout`, with the instant it expires at
+	timeout: Option<(Duration, Instant)>,
+}
+
+// Processes left running by a script would otherwise outlive it unnoticed
+impl Drop for Vm {
+	fn drop(&mut self) {
+		for process in self.children.values_mut() {
+			let _ = process.kill();
+			let _ = process.wait();
+		}
+	}
+}
+
+impl Default for Vm {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl Vm {
@@ -69,14 +200,151 @@ impl Vm {
 			global_scope: Scope::new(),
 			stack_scope: None,
 			builtins: HashMap::new(),
+			aliases: HashMap::new(),
 			allow_var_shadowing: false,
 			allow_implicit_var: false,
 			root_package_location: Location::new_z(0, 0, "_vm".into()),
+			input: None,
+			output: Box::new(std::io::stdout()),
+			error_output: Box::new(std::io::stderr()),
+			args: Vec::new(),
+			children: HashMap::new(),
+			rng_state: builtins::time_seed(),
+			steps: 0,
+			step_limit: None,
+			timeout: None,
 		}
 	}
 
+	/// Allows `let` to redeclare a variable that already exists in the same scope.
+	pub fn set_var_shadowing(&mut self, allow: bool) {
+		self.allow_var_shadowing = allow;
+	}
+
+	/// Allows assigning to an undeclared variable, which then declares it in the current scope.
+	pub fn set_implicit_var(&mut self, allow: bool) {
+		self.allow_implicit_var = allow;
+	}
+
+	/// Sets the stream scripts read from with `read_all`.
+	pub fn set_input(&mut self, input: Box<dyn Read>) {
+		self.input = Some(input);
+	}
+
+	/// Limits how many steps (statements, expressions and blocks evaluated) scripts may take,
+	/// so that an endless loop can't hang the host. The count is cumulative over every
+	/// `exec_package` call and is only reset by setting a limit again; `None` removes it.
+	pub fn set_step_limit(&mut self, limit: Option<u64>) {
+		self.steps = 0;
+		self.step_limit = limit;
+	}
+
+	/// Stops scripts once `timeout` has elapsed from now, so that a slow or endless loop can't
+	/// hang the host. Like the step limit, it spans every following `exec_package` call; `None`
+	/// removes it. The clock is only read every few hundred steps, so a single long builtin call
+	/// (e.g. `sleep`) can overrun it.
+	pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+		self.timeout = timeout.map(|timeout| (timeout, Instant::now() + timeout));
+	}
+
+	/// Sets where `println` writes to, instead of stdout.
+	pub fn set_output(&mut self, output: Box<dyn Write>) {
+		self.output = output;
+	}
+
+	/// Sets where diagnostics are written to, instead of stderr.
+	pub fn set_error_output(&mut self, error_output: Box<dyn Write>) {
+		self.error_output = error_output;
+	}
+
+	/// Writes a diagnostic line to the error sink. A failure to write it is ignored, as there
+	/// would be nowhere left to report it.
+	pub fn report_error(&mut self, message: impl Display) {
+		let _ = writeln!(self.error_output, "{message}");
+	}
+
+	/// Sets the arguments scripts get from `args`.
+	pub fn set_args(&mut self, args: Vec<String>) {
+		self.args = args;
+	}
+
+	/// Declares a global variable, or overwrites it (constant or not) if it already exists,
+	/// regardless of the shadowing flag.
+	pub fn set_global(&mut self, name: String, value: VmVariant) {
+		self.global_scope
+			.variables
+			.insert(name, StoredValue::variable(value));
+	}
+
+	/// Reads a global variable, ignoring any local scope.
+	pub fn get_global(&self, name: &str) -> Option<VmVariant> {
+		self.global_scope
+			.variables
+			.get(name)
+			.map(|stored| stored.value.clone())
+	}
+
+	/// Makes `name` call `target`, a builtin or a function visible from the current scope.
+	/// Aliases are global, and an alias of an alias points to the original function.
+	pub fn alias_function(&mut self, name: String, target: String) -> VmResult<()> {
+		let target = self.aliases.get(&target).cloned().unwrap_or(target);
+
+		let target_exists = self.builtins.contains_key(&target)
+			|| self
+				.stack_scope
+				.as_ref()
+				.is_some_and(|scope| scope.has_function(&target))
+			|| self.global_scope.has_function(&target);
+
+		if !target_exists {
+			return Err(VmError::unknown_identifier(target));
+		}
+
+		if self.builtins.contains_key(&name)
+			|| self.aliases.contains_key(&name)
+			|| self.global_scope.has_function(&name)
+		{
+			return Err(VmError::func_name_dup(name));
+		}
+
+		self.aliases.insert(name, target);
+
+		Ok(())
+	}
+
+	/// Names of the global variables and constants, sorted.
+	pub fn global_variable_names(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = self
+			.global_scope
+			.variables
+			.keys()
+			.map(String::as_str)
+			.collect();
+
+		names.sort_unstable();
+		names
+	}
+
+	/// Names of the functions declared by scripts (builtins excluded), sorted.
+	pub fn global_function_names(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = self
+			.global_scope
+			.functions
+			.keys()
+			.map(String::as_str)
+			.collect();
+
+		names.sort_unstable();
+		names
+	}
+
+	/// Where the current function was called from. Outside of functions, this is the statement
+	/// being run, so that builtins called at top level still report a real location.
 	pub fn caller_location(&self) -> Location {
-		self.get_scope().caller.clone()
+		self.get_scope()
+			.caller
+			.clone()
+			.unwrap_or_else(|| self.root_package_location.clone())
 	}
 
 	fn get_scope(&self) -> &Scope {
@@ -95,115 +363,431 @@ impl Vm {
 		}
 	}
 
-	pub fn exec_package(
-		&mut self,
-		located_package: LocatedType<ParsedHighLevel>,
-	) -> VmResult<Option<VmVariant>> {
+	pub fn exec_package(&mut self, located_package: LocatedType<ParsedHighLevel>) -> VmResult<()> {
+		self.exec_statement(&located_package)
+	}
+
+	// The AST is only borrowed while evaluating, so that loop bodies and function bodies run
+	// again without being copied
+	fn exec_statement(&mut self, located_package: &LocatedType<ParsedHighLevel>) -> VmResult<()> {
 		// let source: String = package.source;
 
-		self.root_package_location = located_package.location;
-		let package = located_package.inner;
-
-		let ret = match package {
-			ParsedHighLevel::VarDecl(assign_data) => self
-				.eval_var_assign(assign_data, Self::new_variable)
-				.map(|_| Option::None)?,
-			ParsedHighLevel::VarSet(assign_data) => self
-				.eval_var_assign(assign_data, Self::set_variable)
-				.map(|_| Option::None)?,
-			ParsedHighLevel::FuncCall(call_data) => self.eval_func_call(call_data).map(|v| {
-				if self.stack_scope.is_none() {
-					Option::Some(v)
-				} else {
-					Option::None
-				}
-			})?,
-			ParsedHighLevel::FuncDecl(func_decl) => {
-				self.eval_func_decl(func_decl).map(|_| Option::None)?
+		self.root_package_location = located_package.location.clone();
+		let package = &located_package.inner;
+
+		self.step()?;
+
+		match package {
+			ParsedHighLevel::VarDecl(assign_data) => {
+				self.eval_var_assign(assign_data, Self::new_variable)?
 			}
-			ParsedHighLevel::If(if_statement) => {
-				self.eval_if(if_statement).map(|_| Option::None)?
+			ParsedHighLevel::ConstDecl(assign_data) => {
+				self.eval_var_assign(assign_data, Self::new_constant)?
 			}
-			ParsedHighLevel::Noop => Option::None,
-		};
+			ParsedHighLevel::VarSet(assign_data) => {
+				self.eval_var_assign(assign_data, Self::set_variable)?
+			}
+			ParsedHighLevel::FuncCall(call_data) => self.eval_func_call(call_data).map(|_| ())?,
+			ParsedHighLevel::FuncDecl(func_decl) => self.eval_func_decl(func_decl.clone())?,
+			ParsedHighLevel::If(if_statement) => self.eval_if(if_statement).map(|_| ())?,
+			ParsedHighLevel::Match(match_statement) => {
+				self.eval_match(match_statement).map(|_| ())?
+			}
+			ParsedHighLevel::Loop(block) => self.eval_loop(block).map(|_| ())?,
+			ParsedHighLevel::Block(block) => self.eval_block(block).map(|_| ())?,
+			ParsedHighLevel::Break(value) => {
+				// Located for when no loop catches it
+				let location = self.root_package_location.clone();
+
+				let value = match value {
+					Some(value) => self.eval_expr(value)?,
+					None => VmVariant::Unit,
+				};
+
+				return Err(VmError::break_loop(value).with_context_location(location));
+			}
+			ParsedHighLevel::Alias(alias) => {
+				let location = self.root_package_location.clone();
+
+				self.alias_function(alias.name.clone(), alias.target.clone())
+					.map_err(|err| err.with_context_location(location))?
+			}
+			ParsedHighLevel::Noop => (),
+		}
 
-		Ok(ret)
+		Ok(())
 	}
 
-	fn eval_var_assign(&mut self, var_assign: VarAssign, vmfunc: VmFuncVarAssign) -> VmResult<()> {
-		let evaluated_val = self.eval_expr(var_assign.val)?;
+	fn step(&mut self) -> VmResult<()> {
+		// Reading the clock on every step would dominate tight loops
+		const TIMEOUT_CHECK_INTERVAL: u64 = 256;
+
+		self.steps += 1;
+
+		if let Some(limit) = self.step_limit.filter(|limit| self.steps > *limit) {
+			return Err(VmError::step_limit_exceeded(limit))
+				.with_context_location(self.root_package_location.clone());
+		}
+
+		if let Some((timeout, deadline)) = self.timeout {
+			if self.steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && Instant::now() >= deadline {
+				return Err(VmError::timeout(timeout))
+					.with_context_location(self.root_package_location.clone());
+			}
+		}
 
-		vmfunc(self, var_assign.name, evaluated_val)
+		Ok(())
 	}
 
-	fn eval_func_call(&mut self, mut func_call_expr: FuncCallExpr) -> VmResult<VmVariant> {
+	fn eval_var_assign(&mut self, var_assign: &VarAssign, vmfunc: VmFuncVarAssign) -> VmResult<()> {
+		// Blocks in the value move `root_package_location` to their own statements
+		let location = self.root_package_location.clone();
+		let evaluated_val = self.eval_expr(&var_assign.val)?;
+
+		vmfunc(self, var_assign.name.clone(), evaluated_val).with_context_location(location)
+	}
+
+	fn eval_func_call(&mut self, func_call_expr: &FuncCallExpr) -> VmResult<VmVariant> {
 		let mut params: Vec<VmVariant> = Vec::with_capacity(func_call_expr.args.len());
 
-		for arg_expr in func_call_expr.args.drain(..) {
+		for arg_expr in &func_call_expr.args {
 			params.push(self.eval_expr(arg_expr)?);
 		}
 
-		self.call_func(func_call_expr.name, params)
+		self.call_func(func_call_expr.name.clone(), params)
 	}
 
 	fn eval_func_decl(&mut self, func_decl: FuncDecl) -> VmResult<()> {
+		let (name, func_data): (String, FunctionData) = func_decl
+			.try_into()
+			.with_context_location(self.root_package_location.clone())?;
+
+		// Builtins and aliases are resolved first when calling, so they would silently hide it
+		if self.builtins.contains_key(&name) || self.aliases.contains_key(&name) {
+			return Err(VmError::func_name_dup(name))
+				.with_context_location(self.root_package_location.clone());
+		}
+
 		let scope = if let Some(scope) = self.stack_scope.as_mut() {
 			scope
 		} else {
 			&mut self.global_scope
 		};
 
-		let (name, func_data) = func_decl.into();
+		let overloads = scope.functions.entry(name.clone()).or_default();
 
-		if scope.functions.contains_key(&name) {
-			return Err(VmError::func_name_dup(name));
+		// Only the same name with the same arity is a duplicate
+		if overloads.contains_key(&func_data.args.len()) {
+			return Err(VmError::func_name_dup(name))
+				.with_context_location(self.root_package_location.clone());
 		}
 
-		scope.functions.insert(name, Rc::new(func_data));
+		overloads.insert(func_data.args.len(), Rc::new(func_data));
 
 		Ok(())
 	}
 
-	fn eval_if(&mut self, mut if_statement: IfStatement) -> VmResult<()> {
-		let cond_variant = self.eval_expr(if_statement.val)?;
+	// Evaluates to the value of the taken branch, or Unit when the condition is false and there
+	// is no else branch.
+	fn eval_if(&mut self, if_statement: &IfStatement) -> VmResult<VmVariant> {
+		let cond_variant = self.eval_expr(&if_statement.val)?;
 
 		if cond_variant.try_native()? {
-			let old_scope = self.stack_scope.take();
-
-			self.stack_scope = Some(Scope::new());
+			self.eval_block(&if_statement.block)
+		} else if let Some(else_block) = &if_statement.else_block {
+			self.eval_block(else_block)
+		} else {
+			Ok(VmVariant::Unit)
+		}
+	}
 
-			for package in if_statement.block.statements.drain(..) {
-				self.exec_package(package)?;
+	fn eval_loop(&mut self, block: &StatementBlock) -> VmResult<VmVariant> {
+		loop {
+			if let Err(err) = self.eval_block(block) {
+				return err.into_break_value();
 			}
+		}
+	}
+
+	// Runs the first arm whose pattern matches and whose guard, if any, holds. A literal or
+	// range pattern of a type that can't be compared with the value simply doesn't match, and
+	// a match without any matching arm does nothing.
+	fn eval_match(&mut self, match_statement: &MatchStatement) -> VmResult<()> {
+		let value = self.eval_expr(&match_statement.val)?;
 
-			self.stack_scope = old_scope;
+		for arm in &match_statement.arms {
+			if self.eval_match_arm(&value, arm)? {
+				break;
+			}
 		}
 
 		Ok(())
 	}
 
-	fn eval_array(&mut self, mut array_data: ArrayExpr) -> VmResult<VmVariant> {
+	fn eval_match_arm(&mut self, value: &VmVariant, arm: &MatchArm) -> VmResult<bool> {
+		let mut binding = None;
+
+		match &arm.pattern {
+			MatchPattern::Wildcard => (),
+			MatchPattern::Binding(name) => binding = Some(name),
+			MatchPattern::Literal(expr) => {
+				let pattern = self.eval_expr(expr)?;
+
+				if value.compare(&pattern) != Some(Ordering::Equal) {
+					return Ok(false);
+				}
+			}
+			MatchPattern::Range {
+				start,
+				end,
+				inclusive,
+			} => {
+				let start = self.eval_expr(start)?;
+				let end = self.eval_expr(end)?;
+
+				let after_start = matches!(
+					value.compare(&start),
+					Some(Ordering::Greater | Ordering::Equal)
+				);
+				let before_end = match value.compare(&end) {
+					Some(Ordering::Less) => true,
+					Some(Ordering::Equal) => *inclusive,
+					_ => false,
+				};
+
+				if !after_start || !before_end {
+					return Ok(false);
+				}
+			}
+		}
+
+		self.with_block_scope(|vm| {
+			if let Some(name) = binding {
+				vm.get_scope_mut()
+					.variables
+					.insert(name.clone(), StoredValue::variable(value.clone()));
+			}
+
+			if let Some(guard) = &arm.guard {
+				if !vm.eval_expr(guard)?.try_native::<bool>()? {
+					return Ok(false);
+				}
+			}
+
+			vm.exec_block_content(&arm.block)?;
+
+			Ok(true)
+		})
+	}
+
+	fn eval_block(&mut self, block: &StatementBlock) -> VmResult<VmVariant> {
+		self.with_block_scope(|vm| vm.exec_block_content(block))
+	}
+
+	// Runs `f` with `scope` as the current local scope. The caller's scope and package location
+	// are restored afterwards, even on error, so that nested calls (e.g. a builtin calling back
+	// into a user function) always return to the right frame.
+	fn with_scope<R>(
+		&mut self,
+		scope: Scope,
+		f: impl FnOnce(&mut Self) -> VmResult<R>,
+	) -> VmResult<R> {
+		let previous_scope = self.stack_scope.replace(scope);
+		let previous_location = self.root_package_location.clone();
+
+		let res = f(self);
+
+		self.stack_scope = previous_scope;
+		self.root_package_location = previous_location;
+
+		res
+	}
+
+	// Like `with_scope`, with a block scope that still sees the current one
+	fn with_block_scope<R>(&mut self, f: impl FnOnce(&mut Self) -> VmResult<R>) -> VmResult<R> {
+		let parent = self.stack_scope.take();
+
+		self.stack_scope = Some(Scope::new_block(parent));
+
+		let res = f(self);
+
+		// UNWRAP: the block scope was set just above, and every scope `f` pushed itself has been
+		// restored by now
+		let block = self.stack_scope.take().unwrap();
+
+		self.stack_scope = block.parent.map(|parent| *parent);
+
+		res
+	}
+
+	// Runs the statements of a block in the current scope, then evaluates its tail expression,
+	// if any, as the block's value. This is the only rule giving a block a value, for function
+	// bodies, branches, match arms, loops and bare blocks alike: statements never produce one.
+	fn exec_block_content(&mut self, block: &StatementBlock) -> VmResult<VmVariant> {
+		// Counted even when empty, so that `loop {}` still runs out of steps
+		self.step()?;
+
+		for package in &block.statements {
+			self.exec_statement(package)?;
+		}
+
+		match &block.tail {
+			Some(tail) => {
+				self.root_package_location = tail.location.clone();
+				self.eval_expr(&tail.inner)
+			}
+			None => Ok(VmVariant::Unit),
+		}
+	}
+
+	fn eval_array(&mut self, array_data: &ArrayExpr) -> VmResult<VmVariant> {
 		let elems: Vec<VmVariant> = array_data
 			.args
-			.drain(..)
+			.iter()
 			.map(|e| self.eval_expr(e))
 			.collect::<VmResult<Vec<VmVariant>>>()?;
 
 		Ok(VmVariant::Array(elems))
 	}
 
-	fn eval_expr(&mut self, expr: Expr) -> VmResult<VmVariant> {
+	// Later entries overwrite earlier ones with the same key
+	fn eval_map(&mut self, map_data: &MapExpr) -> VmResult<VmVariant> {
+		let mut map = BTreeMap::new();
+
+		for (key, value) in &map_data.entries {
+			let key: String = self.eval_expr(key)?.try_native()?;
+
+			map.insert(key, self.eval_expr(value)?);
+		}
+
+		Ok(VmVariant::Map(map))
+	}
+
+	// Arrays are indexed by Integer and maps by String
+	fn eval_index(&mut self, index_data: &IndexExpr) -> VmResult<VmVariant> {
+		let location = self.root_package_location.clone();
+		let target = self.eval_expr(&index_data.target)?;
+		let index = self.eval_expr(&index_data.index)?;
+
+		Self::index_variant(target, index).with_context_location(location)
+	}
+
+	fn index_variant(target: VmVariant, index: VmVariant) -> VmResult<VmVariant> {
+		match target {
+			VmVariant::Array(mut array) => {
+				let index: i64 = index.try_native()?;
+
+				// Negative indices count from the end, `-1` being the last element
+				let adjusted = if index < 0 {
+					index + array.len() as i64
+				} else {
+					index
+				};
+
+				match usize::try_from(adjusted) {
+					Ok(idx) if idx < array.len() => Ok(array.swap_remove(idx)),
+					_ => Err(VmError::index_out_of_bounds(index, array.len())),
+				}
+			}
+			VmVariant::Map(mut map) => {
+				let key: String = index.try_native()?;
+
+				map.remove(&key).ok_or_else(|| VmError::unknown_key(key))
+			}
+			target => Err(VmError::invalid_value_type(
+				"Array or Map".to_string(),
+				target.get_typeinfo().to_string(),
+			)),
+		}
+	}
+
+	fn eval_member(&mut self, member: &MemberExpr) -> VmResult<VmVariant> {
+		let location = self.root_package_location.clone();
+		let target = self.eval_expr(&member.target)?;
+
+		let VmVariant::Map(mut map) = target else {
+			return Err(VmError::invalid_value_type(
+				VmType::Map.to_string(),
+				target.get_typeinfo().to_string(),
+			))
+			.with_context_location(location);
+		};
+
+		map.remove(&member.field)
+			.ok_or_else(|| VmError::unknown_key(member.field.clone()))
+			.with_context_location(location)
+	}
+
+	// Both sides must be comparable, so even `1 == "1"` is an error rather than false
+	fn eval_comparison(&mut self, comparison: &ComparisonExpr) -> VmResult<VmVariant> {
+		let location = self.root_package_location.clone();
+		let lhs = self.eval_expr(&comparison.lhs)?;
+		let rhs = self.eval_expr(&comparison.rhs)?;
+
+		// Equality doesn't need an ordering, which gives it a faster path, see
+		// `VmVariant::equals`
+		let res = match comparison.op {
+			ComparisonOp::Equal => lhs.equals(&rhs),
+			ComparisonOp::NotEqual => lhs.equals(&rhs).map(|equal| !equal),
+			ComparisonOp::Less => lhs.compare(&rhs).map(Ordering::is_lt),
+			ComparisonOp::LessEqual => lhs.compare(&rhs).map(Ordering::is_le),
+			ComparisonOp::Greater => lhs.compare(&rhs).map(Ordering::is_gt),
+			ComparisonOp::GreaterEqual => lhs.compare(&rhs).map(Ordering::is_ge),
+		};
+
+		let Some(res) = res else {
+			return Err(VmError::invalid_comparison(
+				lhs.get_typeinfo().to_string(),
+				rhs.get_typeinfo().to_string(),
+			))
+			.with_context_location(location);
+		};
+
+		Ok(VmVariant::Bool(res))
+	}
+
+	fn eval_assign(&mut self, assign: &AssignExpr) -> VmResult<VmVariant> {
+		let location = self.root_package_location.clone();
+		let value = self.eval_expr(&assign.val)?;
+
+		self.set_variable(assign.name.clone(), value.clone())
+			.with_context_location(location)?;
+
+		Ok(value)
+	}
+
+	fn eval_expr(&mut self, expr: &Expr) -> VmResult<VmVariant> {
+		self.step()?;
+
 		Ok(match expr {
-			Expr::IntLiteral(v) => VmVariant::Integer(v),
-			Expr::StringLiteral(v) => VmVariant::new_from_string_expr(&v)?,
-			Expr::BoolLiteral(v) => VmVariant::Bool(v),
-			Expr::Identifier(var_name) => self.get_variable(&var_name)?,
+			Expr::IntLiteral(v) => VmVariant::Integer(*v),
+			Expr::StringLiteral(v) => VmVariant::new_from_string_expr(v)?,
+			Expr::BoolLiteral(v) => VmVariant::Bool(*v),
+			Expr::Identifier(var_name) => self
+				.get_variable(var_name)
+				.with_context_location(self.root_package_location.clone())?,
 			Expr::FuncCall(call_data) => self.eval_func_call(call_data)?,
 			Expr::Array(array_data) => self.eval_array(array_data)?,
+			Expr::Map(map_data) => self.eval_map(map_data)?,
+			Expr::Index(index_data) => self.eval_index(index_data)?,
+			Expr::Member(member) => self.eval_member(member)?,
+			Expr::Comparison(comparison) => self.eval_comparison(comparison)?,
+			Expr::Assign(assign) => self.eval_assign(assign)?,
+			Expr::Block(block) => self.eval_block(block)?,
+			Expr::If(if_statement) => self.eval_if(if_statement)?,
+			Expr::Loop(block) => self.eval_loop(block)?,
 		})
 	}
 
 	pub fn new_variable<T: Into<VmVariant>>(&mut self, var_name: String, value: T) -> VmResult<()> {
+		self.declare_variable(var_name, StoredValue::variable(value.into()))
+	}
+
+	pub fn new_constant<T: Into<VmVariant>>(&mut self, var_name: String, value: T) -> VmResult<()> {
+		self.declare_variable(var_name, StoredValue::constant(value.into()))
+	}
+
+	fn declare_variable(&mut self, var_name: String, stored: StoredValue) -> VmResult<()> {
 		let scope = if let Some(scope) = self.stack_scope.as_mut() {
 			scope
 		} else {
@@ -213,53 +797,54 @@ impl Vm {
 		if !self.allow_var_shadowing && scope.variables.contains_key(&var_name) {
 			Err(VmError::var_name_dup(var_name))
 		} else {
-			let vm_value: VmVariant = value.into();
-
-			// println!("[VM DEBUG] New variable: \"{}\" (value: {:?})", var_name, vm_value);
+			trace!("new variable: {var_name:?} (value: {:?})", stored.value);
 
-			scope.variables.insert(var_name, vm_value);
+			scope.variables.insert(var_name, stored);
 
 			Ok(())
 		}
 	}
 
+	// Assigns to the variable in the scope that declares it, looking at the current scope and the
+	// blocks enclosing it, then the global one. An undeclared variable is declared in the current
+	// scope if implicit variables are allowed.
 	pub fn set_variable<T: Into<VmVariant>>(&mut self, var_name: String, value: T) -> VmResult<()> {
-		if !self.allow_implicit_var
-			&& self
-				.stack_scope
-				.as_ref()
-				.map_or(false, |scope| scope.variables.contains_key(&var_name))
-			&& !self.global_scope.variables.contains_key(&var_name)
-		{
-			return Err(VmError::unknown_identifier(var_name));
-		}
-
 		let vm_value: VmVariant = value.into();
 
-		// println!("[VM DEBUG] Variable update: \"{}\" (new value: {:?})", var_name, vm_value);
+		trace!("variable update: {var_name:?} (new value: {vm_value:?})");
 
-		if let Some(scope) = self.stack_scope.as_mut() {
-			scope
-		} else {
-			&mut self.global_scope
-		}
-		.variables
-		.insert(var_name, vm_value);
+		let stored = self
+			.stack_scope
+			.as_mut()
+			.and_then(|scope| scope.find_variable_mut(&var_name))
+			.or_else(|| self.global_scope.variables.get_mut(&var_name));
 
-		Ok(())
+		match stored {
+			Some(stored) if stored.constant => Err(VmError::assign_to_const(var_name)),
+			Some(stored) => {
+				stored.value = vm_value;
+				Ok(())
+			}
+			None if self.allow_implicit_var => {
+				self.get_scope_mut()
+					.variables
+					.insert(var_name, StoredValue::variable(vm_value));
+				Ok(())
+			}
+			None => Err(VmError::unknown_identifier(var_name)),
+		}
 	}
 
+	// Reads from the current scope and the blocks enclosing it, falling back to the global one.
 	pub fn get_variable(&self, var_name: &String) -> VmResult<VmVariant> {
-		let scope = if let Some(scope) = self.stack_scope.as_ref() {
-			scope
-		} else {
-			&self.global_scope
-		};
-
-		// TODO: Also check global scope if stack_scope
-
-		if let Some(val) = scope.variables.get(var_name) {
-			Ok(val.clone())
+		let stored = self
+			.stack_scope
+			.as_ref()
+			.and_then(|scope| scope.find_variable(var_name))
+			.or_else(|| self.global_scope.variables.get(var_name));
+
+		if let Some(stored) = stored {
+			Ok(stored.value.clone())
 		} else {
 			Err(VmError::unknown_identifier(var_name.clone()))
 		}
@@ -270,71 +855,617 @@ impl Vm {
 		func_name: String,
 		mut params: Vec<VmVariant>,
 	) -> VmResult<VmVariant> {
+		trace!("calling {func_name} with params {params:?}");
+
+		// Builtins dispatching on their name get the original one
+		let func_name = match self.aliases.get(&func_name) {
+			Some(target) => target.clone(),
+			None => func_name,
+		};
+
 		if let Some(builtin_func) = self.builtins.get(&func_name) {
 			return builtin_func(self, func_name, params);
 		}
 
-		let user_func = {
-			if let Some(scope) = self.stack_scope.as_mut() {
+		let user_func = self
+			.stack_scope
+			.as_ref()
+			.and_then(|scope| scope.find_function(&func_name, params.len()))
+			.or_else(|| self.global_scope.find_function(&func_name, params.len()))
+			.cloned();
+
+		if let Some(user_func) = user_func {
+			let call_location = self.root_package_location.clone();
+			let mut scope = Scope::new_subscope(call_location.clone());
+
+			let zipped = user_func
+				.args
+				.iter()
+				.zip(user_func.arg_types.iter())
+				.zip(params.drain(..));
+
+			for ((name, arg_type), value) in zipped {
+				let value_type = value.get_typeinfo();
+
+				if !arg_type.accepts(&value_type) {
+					return Err(VmError::invalid_value_type(
+						arg_type.to_string(),
+						value_type.to_string(),
+					))
+					.with_context_func_arg(call_location, func_name, name.clone());
+				}
+
 				scope
-			} else {
-				&self.global_scope
+					.variables
+					.insert(name.clone(), StoredValue::variable(value));
 			}
-			.functions
-			.get(&func_name)
-			.cloned()
-		};
 
-		// println!("[VM DEBUG] Trying to call {} with params {:?}", func_name, params);
+			// zipped.unzip() when I'll implement default values
+
+			// TODO: Properly clean previous stack scope (when type cleanup is implemented, of course)
 
-		if let Some(user_func) = user_func {
-			let old_scope = self.stack_scope.take();
+			let ret_value = self
+				.with_scope(scope, |vm| vm.exec_block_content(&user_func.block))
+				.map_err(|err| {
+					err.escape_function()
+						.with_call_frame(func_name.clone(), call_location.clone())
+				})?;
+
+			let ret_type = ret_value.get_typeinfo();
+
+			if !user_func.return_type.accepts(&ret_type) {
+				return Err(VmError::invalid_value_type(
+					user_func.return_type.to_string(),
+					ret_type.to_string(),
+				))
+				.with_context_func_call(call_location, func_name);
+			}
 
-			self.stack_scope = Some(Scope::new_subscope(Location::new(0, 0, "_vm".to_string())));
+			return Ok(ret_value);
+		}
 
-			// TODO: Parameters
+		let mut arities = BTreeSet::new();
 
-			if params.len() != user_func.args.len() {
-				return Err(VmError::wrong_arg_count(user_func.args.len(), params.len()))
-					.with_context_func_call(self.caller_location(), func_name);
-			}
+		if let Some(scope) = self.stack_scope.as_ref() {
+			scope.collect_arities(&func_name, &mut arities);
+		}
 
-			let zipped = user_func.args.iter().zip(params.drain(..));
+		self.global_scope.collect_arities(&func_name, &mut arities);
 
-			for (name, value) in zipped {
-				self.stack_scope
-					.as_mut()
-					.unwrap()
-					.variables
-					.insert(name.clone(), value);
+		let err = match arities.len() {
+			0 => {
+				return Err(VmError::unknown_identifier(func_name))
+					.with_context_location(self.root_package_location.clone())
 			}
+			1 => VmError::wrong_arg_count(arities.pop_first().unwrap_or_default(), params.len()),
+			_ => VmError::no_matching_overload(params.len(), arities.into_iter().collect()),
+		};
 
-			// zipped.unzip() when I'll implement default values
+		Err(err).with_context_func_call(self.caller_location(), func_name)
+	}
+}
 
-			let mut res: VmResult<VmVariant> = Ok(VmVariant::Unit);
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, Instant};
+
+	use super::{
+		testing::{assert_evals_to, eval_err, exec, test_vm, SharedBuffer},
+		VmVariant,
+	};
+
+	fn describe(value: &str) -> String {
+		format!(
+			r#"
+				let res = "none";
+				match {value} {{
+					1 => {{ res = "one"; }},
+					"two" => {{ res = "two"; }},
+					true => {{ res = "true"; }},
+					_ => {{ res = "other"; }},
+				}}
+				res
+			"#
+		)
+	}
 
-			for package in &user_func.packages {
-				match self.exec_package(package.clone()) {
-					Ok(Some(v)) => {
-						res = Ok(v);
-						break;
-					}
-					Err(err) => {
-						res = Err(err);
-						break;
-					}
+	#[test]
+	fn match_literals_and_wildcard() {
+		assert_evals_to(&describe("1"), r#""one""#);
+		assert_evals_to(&describe(r#""two""#), r#""two""#);
+		assert_evals_to(&describe("true"), r#""true""#);
+		assert_evals_to(&describe("[1]"), r#""other""#);
+		assert_evals_to(
+			r#"let res = "none"; match 3 { 1 => { res = "one"; } } res"#,
+			r#""none""#,
+		);
+	}
 
-					Ok(None) => (),
-				};
-			}
+	fn classify(value: &str) -> String {
+		format!(
+			r#"
+				let res = "none";
+				match {value} {{
+					0..10 => {{ res = "digit"; }},
+					n if n > 100 => {{ res = "big"; }},
+					10..=20 => {{ res = "teen"; }},
+					n => {{ res = typename(n); }},
+				}}
+				res
+			"#
+		)
+	}
 
-			// TODO: Properly clean previous stack scope (when type cleanup is implemented, of course)
+	#[test]
+	fn match_ranges_and_guards() {
+		assert_evals_to(&classify("0"), r#""digit""#);
+		assert_evals_to(&classify("9"), r#""digit""#);
+		assert_evals_to(&classify("10"), r#""teen""#);
+		assert_evals_to(&classify("20"), r#""teen""#);
+		assert_evals_to(&classify("101"), r#""big""#);
+		// The guard is false, so the later range arm gets the value
+		assert_evals_to(&classify("15"), r#""teen""#);
+		assert_evals_to(&classify("21"), r#""Integer""#);
+	}
+
+	#[test]
+	fn constants_cannot_be_reassigned() {
+		assert_evals_to("const answer = 42; answer", "42");
+
+		let err = eval_err("const answer = 42; answer = 1;");
+		assert!(err.starts_with("test:1:"), "{err}");
+		assert!(err.contains("answer"), "{err}");
+		assert!(eval_err("const answer = 42;\n{ answer = 1; }").starts_with("test:2:3:"));
+	}
+
+	#[test]
+	fn var_shadowing_flag() {
+		let (mut vm, _) = test_vm();
+
+		assert!(exec(&mut vm, "let x = 1; let x = 2;")
+			.unwrap_err()
+			.to_string()
+			.contains("duplicate variable: x"));
+
+		vm.set_var_shadowing(true);
+		exec(&mut vm, "let y = 1; let y = \"two\";").unwrap();
+		assert_eq!(vm.get_global("y").unwrap().to_string(), "two");
+	}
+
+	#[test]
+	fn implicit_var_flag() {
+		let (mut vm, _) = test_vm();
+
+		assert!(exec(&mut vm, "x = 1;")
+			.unwrap_err()
+			.to_string()
+			.contains("x"));
+		assert!(vm.get_global("x").is_none());
+
+		vm.set_implicit_var(true);
+		exec(&mut vm, "x = 1;").unwrap();
+		assert_eq!(vm.get_global("x").unwrap().to_string(), "1");
+	}
+
+	#[test]
+	fn implicit_var_scope() {
+		let (mut vm, _) = test_vm();
+
+		vm.set_implicit_var(true);
+		exec(
+			&mut vm,
+			r#"
+				let outer = 0;
+				fn set_local() { local = 1; local }
+				let from_fn = set_local();
+				let from_block = { inner = 2; outer = inner; inner };
+			"#,
+		)
+		.unwrap();
+
+		assert_eq!(vm.get_global("from_fn").unwrap().to_string(), "1");
+		assert_eq!(vm.get_global("from_block").unwrap().to_string(), "2");
+		// Assigning to an existing variable still updates it rather than declaring another one
+		assert_eq!(vm.get_global("outer").unwrap().to_string(), "2");
+		// Implicit declarations belong to the scope they were made in
+		assert!(vm.get_global("local").is_none());
+		assert!(vm.get_global("inner").is_none());
+
+		vm.set_implicit_var(false);
+		assert!(exec(&mut vm, "if true { other = 1; }")
+			.unwrap_err()
+			.to_string()
+			.starts_with("test:1:11: unknown identifier: other"));
+	}
+
+	#[test]
+	fn block_expressions() {
+		assert_evals_to("let x = { let y = [1]; y }; x", "[1]");
+		assert_evals_to("let x = { }; typename(x)", r#""Map""#);
+		assert_evals_to(
+			r#"let x = { println("side effect"); }; typename(x)"#,
+			r#""Unit""#,
+		);
+		assert_evals_to("let x = { { 5 } }; x", "5");
+		// Variables declared in a block don't outlive it
+		assert!(eval_err("let x = { let y = 1; y }; y").contains("unknown identifier: y"));
+	}
+
+	#[test]
+	fn if_expressions() {
+		assert_evals_to(r#"if 1 < 2 { "yes" } else { "no" }"#, r#""yes""#);
+		assert_evals_to(r#"let x = if false { "yes" } else { "no" }; x"#, r#""no""#);
+		assert_evals_to(
+			r#"let x = if false { 1 } else { if true { 2 } else { 3 } }; x"#,
+			"2",
+		);
+		// Without an else, a false condition gives Unit
+		assert_evals_to("let x = if false { 1 }; typename(x)", r#""Unit""#);
+		assert!(eval_err("let x = if 1 { 1 } else { 2 };").contains("Bool"));
+	}
+
+	#[test]
+	fn maps() {
+		assert_evals_to(r#"let m = { "a": 1, "b": [2] }; m["b"]"#, "[2]");
+		assert_evals_to(r#"let m = { "a": 1, "a": 3 }; m.a"#, "3");
+		assert_evals_to(r#"typename({ "a": 1 })"#, r#""Map""#);
+		assert_evals_to(r#"{ "a": 1, "b": 2 } == { "b": 2, "a": 1 }"#, "true");
+		assert_evals_to(
+			r#"let m = { "a": { "b": "nested" } }; m.a.b"#,
+			r#""nested""#,
+		);
+
+		assert!(eval_err(r#"let m = { "a": 1 }; m["z"]"#).starts_with("test:1:"));
+		assert!(eval_err(r#"let m = { "a": 1 }; m.z"#).contains("z"));
+		assert!(eval_err(r#"let m = { "a": 1 }; m[0]"#).starts_with("test:1:"));
+		assert!(eval_err("let a = [1]; a.z").starts_with("test:1:"));
+		assert!(eval_err(r#"5["a"]"#).starts_with("test:1:"));
+	}
+
+	#[test]
+	fn global_accessors() {
+		let (mut vm, output) = test_vm();
 
-			self.stack_scope = old_scope;
+		vm.set_global("host_value".to_string(), VmVariant::Integer(7));
+		exec(
+			&mut vm,
+			"println(host_value); const fixed = 1; let from_script = [host_value];",
+		)
+		.unwrap();
 
-			return res;
+		assert_eq!(output.contents(), "7\n");
+		assert_eq!(vm.get_global("from_script").unwrap().to_string(), "[7]");
+		assert!(vm.get_global("missing").is_none());
+
+		// Unlike scripts, the host may overwrite constants
+		vm.set_global("fixed".to_string(), VmVariant::Integer(2));
+		assert_eq!(vm.get_global("fixed").unwrap().to_string(), "2");
+
+		// Locals stay out of reach
+		exec(&mut vm, "fn f() { let local = 1; }\nf();").unwrap();
+		assert!(vm.get_global("local").is_none());
+	}
+
+	#[test]
+	fn comparisons() {
+		assert_evals_to("1 == 1", "true");
+		assert_evals_to("1 < 2", "true");
+		assert_evals_to(r#""abc" >= "abd""#, "false");
+		assert_evals_to("[1, 2] <= [1, 2]", "true");
+		assert_evals_to("true != false", "true");
+
+		let err = eval_err(r#"1 == "1""#);
+		assert!(err.starts_with("test:1:"), "{err}");
+		assert!(err.contains("cannot compare Integer with String"), "{err}");
+		assert!(eval_err(r#"[1] < ["a"]"#).contains("cannot compare"));
+	}
+
+	#[test]
+	fn chained_assignment() {
+		assert_evals_to("let a = 0; let b = 0; a = b = 5; [a, b]", "[5, 5]");
+		assert_evals_to("let a = 0; let b = (a = [1]); b", "[1]");
+		assert_evals_to("let a = 0; println(a = 2); a", "2");
+		assert!(eval_err("let a = 0;\nprintln(a = c = 1);")
+			.starts_with("test:2:1: unknown identifier: c"));
+	}
+
+	#[test]
+	fn errors_go_to_the_error_sink() {
+		let (mut vm, output) = test_vm();
+		let error_output = SharedBuffer::default();
+
+		vm.set_error_output(Box::new(error_output.clone()));
+
+		let err = exec(&mut vm, "println(\"ok\");\nprintln(missing);").unwrap_err();
+		vm.report_error(format_args!("Vm error: {err}"));
+
+		assert_eq!(output.contents(), "ok\n");
+		assert_eq!(
+			error_output.contents(),
+			"Vm error: test:2:1: unknown identifier: missing\n"
+		);
+	}
+
+	#[test]
+	fn negative_indices() {
+		assert_evals_to("[10, 20, 30][-1]", "30");
+		assert_evals_to("[10, 20, 30][-3]", "10");
+		assert_evals_to("[10, 20, 30][0]", "10");
+
+		assert_eq!(
+			eval_err("[1][-5]"),
+			"test:1:16: index out of bounds (index -5, length 1)"
+		);
+		assert!(eval_err("[1][1]").contains("index out of bounds (index 1, length 1)"));
+		assert!(eval_err("[][-1]").contains("index out of bounds"));
+	}
+
+	#[test]
+	fn top_level_blocks() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			r#";;; {} { let inner = 1; println("x"); } let v = {};
+			fn f() { {} { println("y"); } println("z"); } f();"#,
+		)
+		.unwrap();
+
+		assert_eq!(output.contents(), "x\ny\nz\n");
+		// The block has its own scope, while `{}` in an expression is still a map
+		assert!(vm.get_global("inner").is_none());
+		assert!(matches!(vm.get_global("v"), Some(VmVariant::Map(_))));
+	}
+
+	#[test]
+	fn chained_postfix() {
+		assert_evals_to(
+			r#"let m = { "b": [{ "c": [3, 1, 2] }] }; m.b[0].c.sort()[2]"#,
+			"3",
+		);
+		assert_evals_to(r#"let m = { "a": [[1], [2, 3]] }; m["a"][1][0]"#, "2");
+		assert_evals_to(r#"[[1], [2]].flatten().reverse()[0]"#, "2");
+		assert!(eval_err(r#"let m = { "b": [] }; m.b[0].c"#).starts_with("test:1:"));
+	}
+
+	#[test]
+	fn errors_carry_the_call_stack() {
+		let (mut vm, _) = test_vm();
+
+		let err = exec(
+			&mut vm,
+			"fn inner() { x }\nfn outer() {\n\tinner()\n}\nouter();",
+		)
+		.unwrap_err();
+
+		assert_eq!(
+			err.to_string(),
+			concat!(
+				"test:1:14: unknown identifier: x\n",
+				"  in inner (called at test:3:2)\n",
+				"  in outer (called at test:5:1)",
+			)
+		);
+	}
+
+	#[test]
+	fn overloads_by_arity() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			"fn f(a) { [a] }
+			fn f(a, b) { [a, b] }
+			fn f() { 0 }
+			println(f(1), f(1, 2), f());",
+		)
+		.unwrap();
+		assert_eq!(output.contents(), "[1] [1, 2] 0\n");
+
+		let err = exec(&mut vm, "f(1, 2, 3);").unwrap_err().to_string();
+		assert!(err.contains("no overload takes 3 arguments (declared arities: [0, 1, 2])"));
+
+		// Only the same arity counts as a duplicate
+		let err = exec(&mut vm, "let x = 1; fn f(c) { c }")
+			.unwrap_err()
+			.to_string();
+		assert_eq!(err, "test:1:12: duplicate function: f");
+	}
+
+	#[test]
+	fn loop_values() {
+		assert_evals_to("let x = loop { break 5; }; x", "5");
+		assert_evals_to("let x = loop { break; }; typename(x)", r#""Unit""#);
+		assert_evals_to(
+			"let a = []; let x = loop { a = flatten([a, [1]]); if a == [1, 1] { break a; } }; x",
+			"[1, 1]",
+		);
+		assert_evals_to("let x = loop { loop { break 1; }; break 2; }; x", "2");
+
+		assert_eq!(eval_err("break 1;"), "test:1:16: break outside of a loop");
+		assert!(eval_err("fn f() { break; } loop { f(); }")
+			.starts_with("test:1:25: break outside of a loop\n  in f"));
+	}
+
+	#[test]
+	fn aliases() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"alias say = println;
+			fn twice(a) { [a, a] }
+			alias double = twice;
+			alias again = double;
+			say("a", [1]);
+			println("a", [1]);
+			say(double(1), again(2));"#,
+		)
+		.unwrap();
+		assert_eq!(output.contents(), "a [1]\na [1]\n[1, 1] [2, 2]\n");
+
+		assert_eq!(
+			exec(&mut vm, "alias nothing = missing;")
+				.unwrap_err()
+				.to_string(),
+			"test:1:1: unknown identifier: missing"
+		);
+		assert!(exec(&mut vm, "alias say = typename;").is_err());
+		assert!(exec(&mut vm, "alias println = typename;").is_err());
+
+		// The host can alias too
+		vm.alias_function("type_of".to_string(), "typename".to_string())
+			.unwrap();
+		exec(&mut vm, r#"assert_eq(type_of(1), typename(1));"#).unwrap();
+		assert!(vm
+			.alias_function("broken".to_string(), "missing".to_string())
+			.is_err());
+	}
+
+	#[test]
+	fn non_literal_expressions_do_not_panic() {
+		// Each of these used to reach an `unimplemented!()` conversion
+		assert_evals_to("let a = 1; [a, [a]]", "[1, [1]]");
+		assert_evals_to(r#"let m = { "k": [typename(1)] }; m.k[0]"#, r#""Integer""#);
+		assert_evals_to(
+			"let a = 1; [a == 1, a = 2, { a }, if true { a }]",
+			"[true, 2, 2, 2]",
+		);
+		assert_evals_to("loop { break [1]; }", "[1]");
+
+		// And these fail with errors instead
+		for source in [
+			"unknown",
+			"unknown_fn()",
+			"[unknown]",
+			r#"{ "k": unknown }"#,
+			"[1][unknown]",
+			"unknown.field",
+			"unknown == 1",
+			"unknown = 1",
+			"{ unknown }",
+			"if unknown { 1 }",
+			"loop { unknown }",
+		] {
+			assert!(eval_err(source).starts_with("test:1:"), "{source}");
 		}
+	}
+
+	#[test]
+	fn vary_and_concrete_parameters() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"fn tag(label: String, value: Vary, extra) { [label, value, extra] }
+			println(tag("a", 1, true), tag("b", [2], "x"), tag("c", { "k": 3 }, 4));"#,
+		)
+		.unwrap();
+		assert_eq!(
+			output.contents(),
+			"[a, 1, true] [b, [2], x] [c, {k: 3}, 4]\n"
+		);
+
+		assert_eq!(
+			exec(&mut vm, "tag(1, 1, 1);").unwrap_err().to_string(),
+			"test:1:1: in tag, at argument label: unexpected type (expected String, got Integer)"
+		);
+	}
+
+	#[test]
+	fn type_annotations() {
+		let (mut vm, output) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"fn wrap(value: Integer): Array { [value] }
+			fn lie(value): String { value }
+			println(wrap(1), lie("ok"));"#,
+		)
+		.unwrap();
+		assert_eq!(output.contents(), "[1] ok\n");
+
+		let mut err = |source| exec(&mut vm, source).unwrap_err().to_string();
+
+		assert_eq!(
+			err(r#"wrap("1");"#),
+			"test:1:1: in wrap, at argument value: unexpected type (expected Integer, got String)"
+		);
+		assert_eq!(
+			err("lie(1);"),
+			"test:1:1: in lie: unexpected type (expected String, got Integer)"
+		);
+		assert_eq!(
+			err("fn bad(a: Strin) { a }"),
+			"test:1:1: unknown type: Strin"
+		);
+	}
+
+	#[test]
+	fn block_tail_is_the_only_value_rule() {
+		let (mut vm, _) = test_vm();
+
+		exec(
+			&mut vm,
+			r#"fn body() { let a = 1; [a] }
+			fn body_statement() { println("no value"); }
+			let results = [
+				body(),
+				typename(body_statement()),
+				if true { let b = 2; [b] } else { 0 },
+				if false { 0 } else { let c = 3; [c] },
+				typename(if true { println("no value"); } else { 0 }),
+				{ let d = 4; [d] },
+				typename({ let e = 5; }),
+				{ { [6] } },
+				loop { break [7]; },
+			];"#,
+		)
+		.unwrap();
+
+		assert_eq!(
+			vm.get_global("results").unwrap().to_string(),
+			"[[1], Unit, [2], [3], Unit, [4], Unit, [6], [7]]"
+		);
+	}
+
+	#[test]
+	fn step_limit() {
+		let (mut vm, _) = test_vm();
+
+		vm.set_step_limit(Some(1000));
+		assert_eq!(
+			exec(&mut vm, "println(1);\nloop {}")
+				.unwrap_err()
+				.to_string(),
+			"test:2:1: step limit exceeded (1000 steps)"
+		);
+
+		// The count is cumulative, until a limit is set again
+		vm.set_step_limit(Some(1000));
+		exec(&mut vm, "fn tick() { 1 }").unwrap();
+		assert!(exec(&mut vm, "loop { tick(); }")
+			.unwrap_err()
+			.to_string()
+			.contains("step limit exceeded"));
+		assert!(exec(&mut vm, "println(1);").is_err());
+
+		vm.set_step_limit(None);
+		assert!(exec(&mut vm, "println(1);").is_ok());
+	}
+
+	#[test]
+	fn timeout() {
+		let (mut vm, _) = test_vm();
+
+		vm.set_timeout(Some(Duration::from_millis(20)));
+		let start = Instant::now();
+		let err = exec(&mut vm, "loop { sleep(1); }").unwrap_err();
+
+		assert_eq!(err.to_string(), "test:1:8: timed out after 20ms");
+		assert!(start.elapsed() >= Duration::from_millis(20));
 
-		Err(VmError::unknown_identifier(func_name))
+		vm.set_timeout(None);
+		assert!(exec(&mut vm, "sleep(30); println(1);").is_ok());
 	}
 }
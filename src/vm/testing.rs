@@ -0,0 +1,100 @@
+//! Helpers for the VM's unit tests: running scripts in a fresh VM and capturing what they print.
+
+use std::{
+	cell::RefCell,
+	cmp::Ordering,
+	io::{Cursor, Write},
+	path::PathBuf,
+	rc::Rc,
+};
+
+use crate::{lexer::Lexer, parser::Parser};
+
+use super::{error::VmResult, variant::VmVariant, Vm};
+
+/// A `Write` sink whose contents stay readable after the VM took ownership of a clone of it.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+	/// Everything written so far, invalid UTF-8 being replaced.
+	pub fn contents(&self) -> String {
+		String::from_utf8_lossy(&self.0.borrow()).into_owned()
+	}
+}
+
+impl Write for SharedBuffer {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.borrow_mut().extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+/// A VM with the default builtins, printing to the returned buffer instead of stdout.
+pub fn test_vm() -> (Vm, SharedBuffer) {
+	let mut vm = Vm::new();
+	let output = SharedBuffer::default();
+
+	vm.register_default_builtins();
+	vm.set_output(Box::new(output.clone()));
+
+	(vm, output)
+}
+
+/// Runs every statement of `source`, stopping at the first error. Tests only feed valid
+/// programs, so a parse error panics.
+pub fn exec(vm: &mut Vm, source: &str) -> VmResult<()> {
+	let lexer = Lexer::new(Cursor::new(source.to_string()), "test".into());
+	let packages = Parser::new(lexer, "test".into())
+		.parse_all()
+		.expect("test script should parse");
+
+	for package in packages {
+		vm.exec_package(package)?;
+	}
+
+	Ok(())
+}
+
+/// Runs `source` as the content of a block in a fresh VM, and returns the block's value.
+pub fn eval(source: &str) -> VmResult<VmVariant> {
+	let (mut vm, _) = test_vm();
+
+	exec(&mut vm, &format!("let result = {{ {source} }};"))?;
+
+	Ok(vm.get_global("result").expect("result should be declared"))
+}
+
+/// Like `eval`, for scripts expected to fail. Returns the error message.
+pub fn eval_err(source: &str) -> String {
+	match eval(source) {
+		Ok(value) => panic!("expected {source:?} to fail, got {value}"),
+		Err(err) => err.to_string(),
+	}
+}
+
+/// Asserts that `source` evaluates to the same value (and type) as `expected`.
+#[track_caller]
+pub fn assert_evals_to(source: &str, expected: &str) {
+	let value = eval(source).unwrap_or_else(|err| panic!("{source:?} failed: {err}"));
+	let expected_value = eval(expected).unwrap_or_else(|err| panic!("{expected:?} failed: {err}"));
+
+	assert!(
+		value.compare(&expected_value) == Some(Ordering::Equal),
+		"{source:?} gave {value}, expected {expected_value}"
+	);
+}
+
+/// An empty directory for `test` to create files in, unique to the test and the process.
+pub fn temp_dir(test: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("ulesl-{test}-{}", std::process::id()));
+
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).expect("temp directory should be creatable");
+
+	dir
+}
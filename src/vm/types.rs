@@ -2,7 +2,10 @@ use std::fmt::Display;
 
 use crate::parser::types::FuncDecl;
 
-use super::FunctionData;
+use super::{
+	error::{VmError, VmResult},
+	FunctionData,
+};
 
 pub trait VmTypable {
 	fn get_typeinfo(&self) -> VmType;
@@ -18,6 +21,31 @@ pub enum VmType {
 	// ReadStream,
 	// WriteStream,
 	Array,
+	Map,
+	Bytes,
+}
+
+impl VmType {
+	/// Looks up a type by the name `typename` gives it.
+	pub fn from_name(name: &str) -> Option<Self> {
+		Some(match name {
+			"Vary" => VmType::Vary,
+			"Unit" => VmType::Unit,
+			"Bool" => VmType::Bool,
+			"Integer" => VmType::Integer,
+			"String" => VmType::String,
+			"Array" => VmType::Array,
+			"Map" => VmType::Map,
+			"Bytes" => VmType::Bytes,
+			_ => return None,
+		})
+	}
+
+	/// Whether a value of type `value_type` can be bound where `self` is expected. `Vary`
+	/// accepts anything, every other type only itself.
+	pub fn accepts(&self, value_type: &VmType) -> bool {
+		*self == VmType::Vary || self == value_type
+	}
 }
 
 impl Display for VmType {
@@ -26,15 +54,32 @@ impl Display for VmType {
 	}
 }
 
-impl From<FuncDecl> for (String, FunctionData) {
-	fn from(value: FuncDecl) -> Self {
-		(
+// Unannotated arguments and return values are `Vary`
+fn resolve_annotation(type_name: Option<String>) -> VmResult<VmType> {
+	match type_name {
+		Some(type_name) => VmType::from_name(&type_name).ok_or(VmError::unknown_type(type_name)),
+		None => Ok(VmType::Vary),
+	}
+}
+
+impl TryFrom<FuncDecl> for (String, FunctionData) {
+	type Error = VmError;
+
+	fn try_from(value: FuncDecl) -> VmResult<Self> {
+		let arg_types = value
+			.arg_types
+			.into_iter()
+			.map(resolve_annotation)
+			.collect::<VmResult<Vec<_>>>()?;
+
+		Ok((
 			value.name,
 			FunctionData {
 				args: value.args,
-				packages: value.block.statements,
-				// return_type: VmType::Vary,
+				arg_types,
+				return_type: resolve_annotation(value.ret_type)?,
+				block: value.block,
 			},
-		)
+		))
 	}
 }
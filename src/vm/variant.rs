@@ -1,10 +1,10 @@
 use std::{
+	cmp::Ordering,
+	collections::BTreeMap,
 	fmt::{Display, Write},
 	rc::Rc,
 };
 
-use crate::parser;
-
 use super::{
 	error::{VmError, VmResult},
 	types::{VmTypable, VmType},
@@ -19,6 +19,9 @@ pub enum VmVariant {
 	// ReadStream(Box<dyn Read>),
 	// WriteStream(Box<dyn Write>),
 	Array(Vec<VmVariant>),
+	Map(BTreeMap<String, VmVariant>),
+	// Raw bytes, for what a String can't hold (e.g. non-UTF-8 process arguments)
+	Bytes(Vec<u8>),
 	Ref(Rc<VmVariant>),
 }
 
@@ -30,6 +33,10 @@ impl<T: IntoVariant> From<T> for VmVariant {
 
 impl VmVariant {
 	pub fn new_from_string_expr(str: &str) -> VmResult<Self> {
+		if let Some(raw_str) = str.strip_prefix('r') {
+			return Ok(Self::String(raw_str[1..(raw_str.len() - 1)].to_owned()));
+		}
+
 		let trimmed_str = &str[1..(str.len() - 1)];
 		let mut res_str = String::with_capacity(trimmed_str.len());
 
@@ -44,7 +51,7 @@ impl VmVariant {
 					'r' => res_str.push('\r'),
 					't' => res_str.push('\t'),
 					'\\' => res_str.push('\\'),
-					'0' => res_str.push('\t'),
+					'0' => res_str.push('\0'),
 					'\'' => res_str.push('\''),
 					'\"' => res_str.push('\"'),
 					_ => return Err(VmError::invalid_escape(str.to_owned(), idx - 1)),
@@ -68,6 +75,115 @@ impl VmVariant {
 	pub fn try_native<T: TryFromVariant>(self) -> VmResult<T> {
 		T::try_from_variant(self)
 	}
+
+	/// Orders two values of the same type. Arrays compare lexicographically, and maps compare
+	/// their entries in key order.
+	/// Returns `None` when the values can't be compared (e.g. an Integer and a String).
+	pub fn compare(&self, other: &VmVariant) -> Option<Ordering> {
+		match (self, other) {
+			(VmVariant::Ref(lhs), rhs) => lhs.as_ref().compare(rhs),
+			(lhs, VmVariant::Ref(rhs)) => lhs.compare(rhs.as_ref()),
+			(VmVariant::Unit, VmVariant::Unit) => Some(Ordering::Equal),
+			(VmVariant::Bool(lhs), VmVariant::Bool(rhs)) => Some(lhs.cmp(rhs)),
+			(VmVariant::Integer(lhs), VmVariant::Integer(rhs)) => Some(lhs.cmp(rhs)),
+			(VmVariant::String(lhs), VmVariant::String(rhs)) => Some(lhs.cmp(rhs)),
+			(VmVariant::Bytes(lhs), VmVariant::Bytes(rhs)) => Some(lhs.cmp(rhs)),
+			(VmVariant::Array(lhs), VmVariant::Array(rhs)) => {
+				for (lhs_elem, rhs_elem) in lhs.iter().zip(rhs.iter()) {
+					match lhs_elem.compare(rhs_elem)? {
+						Ordering::Equal => (),
+						ord => return Some(ord),
+					}
+				}
+
+				Some(lhs.len().cmp(&rhs.len()))
+			}
+			(VmVariant::Map(lhs), VmVariant::Map(rhs)) => {
+				for ((lhs_key, lhs_value), (rhs_key, rhs_value)) in lhs.iter().zip(rhs.iter()) {
+					match lhs_key.cmp(rhs_key) {
+						Ordering::Equal => (),
+						ord => return Some(ord),
+					}
+
+					match lhs_value.compare(rhs_value)? {
+						Ordering::Equal => (),
+						ord => return Some(ord),
+					}
+				}
+
+				Some(lhs.len().cmp(&rhs.len()))
+			}
+			_ => None,
+		}
+	}
+
+	/// Same as `compare(other).map(Ordering::is_eq)`, but faster for the common `==` case:
+	/// strings and bytes are checked for equality without ordering them, and arrays and maps of
+	/// different lengths only need their element types checked, not their contents compared.
+	pub fn equals(&self, other: &VmVariant) -> Option<bool> {
+		match (self, other) {
+			(VmVariant::Ref(lhs), rhs) => lhs.as_ref().equals(rhs),
+			(lhs, VmVariant::Ref(rhs)) => lhs.equals(rhs.as_ref()),
+			(VmVariant::String(lhs), VmVariant::String(rhs)) => Some(lhs == rhs),
+			(VmVariant::Bytes(lhs), VmVariant::Bytes(rhs)) => Some(lhs == rhs),
+			(VmVariant::Array(lhs_array), VmVariant::Array(rhs_array)) => {
+				if lhs_array.len() != rhs_array.len() {
+					return self.unequal_lengths(other);
+				}
+
+				for (lhs_elem, rhs_elem) in lhs_array.iter().zip(rhs_array.iter()) {
+					if !lhs_elem.equals(rhs_elem)? {
+						return Some(false);
+					}
+				}
+
+				Some(true)
+			}
+			(VmVariant::Map(lhs_map), VmVariant::Map(rhs_map)) => {
+				if lhs_map.len() != rhs_map.len() {
+					return self.unequal_lengths(other);
+				}
+
+				for ((lhs_key, lhs_value), (rhs_key, rhs_value)) in
+					lhs_map.iter().zip(rhs_map.iter())
+				{
+					if lhs_key != rhs_key || !lhs_value.equals(rhs_value)? {
+						return Some(false);
+					}
+				}
+
+				Some(true)
+			}
+			(lhs, rhs) => lhs.compare(rhs).map(Ordering::is_eq),
+		}
+	}
+
+	// `compare` walks the common prefix up to the first difference, and fails if it meets
+	// elements that can't be compared on the way. When every pair in the prefix has comparable
+	// types, it can't fail, and the length difference alone makes the values unequal.
+	fn unequal_lengths(&self, other: &VmVariant) -> Option<bool> {
+		if self.always_comparable(other) {
+			Some(false)
+		} else {
+			self.compare(other).map(Ordering::is_eq)
+		}
+	}
+
+	fn always_comparable(&self, other: &VmVariant) -> bool {
+		match (self, other) {
+			(VmVariant::Ref(lhs), rhs) => lhs.always_comparable(rhs),
+			(lhs, VmVariant::Ref(rhs)) => lhs.always_comparable(rhs),
+			(VmVariant::Array(lhs), VmVariant::Array(rhs)) => lhs
+				.iter()
+				.zip(rhs.iter())
+				.all(|(lhs_elem, rhs_elem)| lhs_elem.always_comparable(rhs_elem)),
+			(VmVariant::Map(lhs), VmVariant::Map(rhs)) => lhs
+				.values()
+				.zip(rhs.values())
+				.all(|(lhs_value, rhs_value)| lhs_value.always_comparable(rhs_value)),
+			(lhs, rhs) => std::mem::discriminant(lhs) == std::mem::discriminant(rhs),
+		}
+	}
 }
 
 impl VmTypable for VmVariant {
@@ -78,20 +194,9 @@ impl VmTypable for VmVariant {
 			VmVariant::Integer(_) => VmType::Integer,
 			VmVariant::String(_) => VmType::String,
 			VmVariant::Array(_) => VmType::Array,
-			VmVariant::Ref(_) => todo!(),
-		}
-	}
-}
-
-impl From<parser::types::Expr> for VmVariant {
-	fn from(value: parser::types::Expr) -> Self {
-		match value {
-			parser::types::Expr::IntLiteral(v) => Self::Integer(v),
-			parser::types::Expr::StringLiteral(v) => Self::String(v),
-			parser::types::Expr::BoolLiteral(v) => Self::Bool(v),
-			parser::types::Expr::Array(_) => unimplemented!(),
-			parser::types::Expr::Identifier(_) => unimplemented!(),
-			parser::types::Expr::FuncCall(_) => unimplemented!(),
+			VmVariant::Map(_) => VmType::Map,
+			VmVariant::Bytes(_) => VmType::Bytes,
+			VmVariant::Ref(v) => v.get_typeinfo(),
 		}
 	}
 }
@@ -120,6 +225,26 @@ impl Display for VmVariant {
 
 				f.write_char(']')
 			}
+			VmVariant::Map(map) => {
+				f.write_char('{')?;
+
+				let mut first = true;
+
+				for (key, value) in map {
+					if first {
+						first = false;
+					} else {
+						f.write_str(", ")?;
+					}
+
+					key.fmt(f)?;
+					f.write_str(": ")?;
+					value.fmt(f)?;
+				}
+
+				f.write_char('}')
+			}
+			VmVariant::Bytes(bytes) => write!(f, "b\"{}\"", bytes.escape_ascii()),
 			VmVariant::Ref(v) => v.fmt(f),
 		}
 	}
@@ -184,7 +309,7 @@ impl<T: IntoVariant> IntoVariant for Box<[T]> {
 
 impl<T: IntoVariant + Clone> IntoVariant for &[T] {
 	fn into_variant(self) -> VmVariant {
-		VmVariant::Array(self.into_iter().cloned().map(T::into_variant).collect())
+		VmVariant::Array(self.iter().cloned().map(T::into_variant).collect())
 	}
 }
 
@@ -208,11 +333,7 @@ impl IntoVariant for Box<[VmVariant]> {
 
 impl IntoVariant for &[VmVariant] {
 	fn into_variant(self) -> VmVariant {
-		let mut vec = Vec::with_capacity(self.len());
-
-		vec.clone_from_slice(self);
-
-		VmVariant::Array(vec)
+		VmVariant::Array(self.to_vec())
 	}
 }
 
@@ -222,6 +343,12 @@ impl IntoVariant for Vec<VmVariant> {
 	}
 }
 
+impl IntoVariant for BTreeMap<String, VmVariant> {
+	fn into_variant(self) -> VmVariant {
+		VmVariant::Map(self)
+	}
+}
+
 macro_rules! into_variant_num {
 	($($intty:ty),*) => {$(
 		impl IntoVariant for $intty {
@@ -274,6 +401,7 @@ impl_try_from_variant! {
 	Bool => bool,
 	Array => Vec<VmVariant>,
 	Array => Box<[VmVariant]>,
+	Map => BTreeMap<String, VmVariant>,
 	Integer => i64
 }
 
@@ -295,3 +423,114 @@ impl_try_from_variant! {
 // 		VmType::String
 // 	}
 // }
+
+#[cfg(test)]
+mod tests {
+	use std::cmp::Ordering;
+
+	use super::VmVariant;
+	use crate::vm::testing::{assert_evals_to, eval};
+
+	fn string_literal(literal: &str) -> String {
+		match VmVariant::new_from_string_expr(literal) {
+			Ok(VmVariant::String(string)) => string,
+			res => panic!("{literal} gave {res:?}"),
+		}
+	}
+
+	#[test]
+	fn escapes_and_raw_strings() {
+		assert_eq!(string_literal(r#"r"a\n""#).chars().count(), 3);
+		assert_eq!(string_literal(r#"r"C:\temp\new""#), r"C:\temp\new");
+		assert_eq!(string_literal(r#""a\n""#), "a\n");
+		assert_eq!(string_literal(r#""\t\r\0\\\"\'""#), "\t\r\0\\\"'");
+		assert_eq!(string_literal(r#""C:\\temp""#), r"C:\temp");
+		assert_evals_to(r#"collect(r"a\n")"#, r#"["a", "\\", "n"]"#);
+
+		assert!(VmVariant::new_from_string_expr(r#""C:\temp\q""#)
+			.unwrap_err()
+			.to_string()
+			.contains("escape"));
+	}
+
+	#[test]
+	fn map_keys_print_in_order() {
+		// Whatever order the keys are written or inserted in
+		for source in [
+			r#"{ "b": 1, "c": [2], "a": 3 }"#,
+			r#"{ "c": [2], "a": 3, "b": 1 }"#,
+			r#"insert(insert({ "c": [2] }, "b", 1), "a", 3)"#,
+		] {
+			let map = eval(source).unwrap();
+
+			assert_eq!(map.to_string(), "{a: 3, b: 1, c: [2]}");
+			assert_evals_to(
+				&format!("to_json({source})"),
+				r#""{\"a\":3,\"b\":1,\"c\":[2]}""#,
+			);
+		}
+	}
+
+	#[test]
+	fn equality_matches_compare() {
+		let values = [
+			"1",
+			"2",
+			r#""1""#,
+			r#""a""#,
+			"true",
+			"[]",
+			"[1]",
+			"[1, 2]",
+			"[2, 1]",
+			r#"["a"]"#,
+			r#"["a", "b"]"#,
+			r#"[1, "a"]"#,
+			"[[1]]",
+			"[[1], [2]]",
+			"[[1, 2]]",
+			r#"[["a", "b"]]"#,
+			r#"[[1], "a"]"#,
+			"{}",
+			r#"{ "a": 1 }"#,
+			r#"{ "a": "1" }"#,
+			r#"{ "b": 1 }"#,
+			r#"{ "a": 1, "b": 2 }"#,
+			r#"{ "a": "x", "b": 2 }"#,
+			r#"{ "a": [1] }"#,
+			r#"{ "a": [1, 2] }"#,
+			r#"{ "a": ["x", "y"] }"#,
+			r#"[{ "a": 1 }]"#,
+			r#"[{ "a": "x", "b": 2 }]"#,
+			"bytes([1])",
+			"bytes([1, 2])",
+		];
+
+		for lhs in values {
+			for rhs in values {
+				let lhs_value = eval(lhs).unwrap();
+				let rhs_value = eval(rhs).unwrap();
+
+				assert_eq!(
+					lhs_value.equals(&rhs_value),
+					lhs_value.compare(&rhs_value).map(Ordering::is_eq),
+					"{lhs} == {rhs}"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn equality_on_length_mismatch() {
+		assert_evals_to(r#"[1] == [1, "b"]"#, "false");
+		assert_evals_to(r#"[2] != [1, "b"]"#, "true");
+		assert_evals_to(r#"{ "a": 1 } == { "a": 2, "b": "x" }"#, "false");
+		assert_evals_to(r#"[1, ["a"]] == [1, ["a"]]"#, "true");
+
+		// Elements in the common prefix must still be comparable, as with ordering operators
+		assert!(eval(r#"[1] == ["a", "b"]"#).is_err());
+		assert!(eval(r#"[[1]] == [["a", "b"]]"#).is_err());
+		assert!(eval(r#"{ "a": 1 } == { "a": "x", "b": 2 }"#).is_err());
+		assert!(eval(r#"[1] < ["a", "b"]"#).is_err());
+	}
+}
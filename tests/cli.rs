@@ -0,0 +1,125 @@
+//! Runs the `easy-scripting-language` binary on scripts written to a temporary directory.
+
+use std::{
+	path::PathBuf,
+	process::{Command, Output},
+};
+
+/// Writes `source` to a script file unique to `name`, in a directory unique to this test run.
+fn write_script(name: &str, source: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("ulesl-cli-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+
+	let path = dir.join(format!("{name}.ulesl"));
+	std::fs::write(&path, source).unwrap();
+
+	path
+}
+
+fn interpreter() -> Command {
+	Command::new(env!("CARGO_BIN_EXE_easy-scripting-language"))
+}
+
+/// Runs `source` with `args` passed to the script.
+fn run_script(name: &str, source: &str, args: &[&str]) -> Output {
+	interpreter()
+		.arg(write_script(name, source))
+		.args(args)
+		.output()
+		.expect("the interpreter should run")
+}
+
+fn stdout(output: &Output) -> String {
+	String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn script_args() {
+	let output = run_script("args", "println(args());", &["one", "two words", "--trace"]);
+
+	assert!(output.status.success());
+	assert_eq!(stdout(&output), "[one, two words, --trace]\n");
+	assert_eq!(
+		stdout(&run_script("no_args", "println(args());", &[])),
+		"[]\n"
+	);
+}
+
+#[test]
+fn exit_status() {
+	let status = |name: &str, source: &str| run_script(name, source, &[]).status.code();
+
+	assert_eq!(status("exit_ok", "println(1);"), Some(0));
+	assert_eq!(
+		status("exit_explicit", "exit(3);\nprintln(\"unreachable\");"),
+		Some(3)
+	);
+	assert_eq!(status("exit_error", "println(missing);"), Some(1));
+	// `exit` takes precedence over an earlier error
+	assert_eq!(
+		status("exit_after_error", "println(missing);\nexit(4);"),
+		Some(4)
+	);
+	assert_eq!(
+		status("exit_zero_after_error", "println(missing);\nexit(0);"),
+		Some(0)
+	);
+	assert_eq!(status("exit_parse_error", "let = ;"), Some(1));
+
+	let output = run_script(
+		"exit_output",
+		"println(\"before\");\nexit(2);\nprintln(\"after\");",
+		&[],
+	);
+	assert_eq!(stdout(&output), "before\n");
+}
+
+#[test]
+fn trace_only_writes_to_stderr() {
+	let path = write_script("trace", "let x = [1, 2];\nfn f(a) { a }\nprintln(f(x));");
+
+	let plain = interpreter().arg(&path).output().unwrap();
+	let flag = interpreter().arg("--trace").arg(&path).output().unwrap();
+	let env = interpreter()
+		.arg(&path)
+		.env("ULESL_TRACE", "1")
+		.output()
+		.unwrap();
+
+	assert_eq!(stdout(&plain), "[1, 2]\n");
+	assert!(plain.stderr.is_empty());
+
+	for traced in [flag, env] {
+		assert_eq!(stdout(&traced), stdout(&plain));
+		assert!(String::from_utf8_lossy(&traced.stderr).contains("[trace]"));
+	}
+}
+
+#[test]
+fn missing_script() {
+	let output = interpreter()
+		.arg(std::env::temp_dir().join("ulesl-cli-missing/none.ulesl"))
+		.output()
+		.expect("the interpreter should run");
+	let stderr = String::from_utf8_lossy(&output.stderr);
+
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr.starts_with("ulesl: could not open "), "{stderr}");
+	assert!(!stderr.contains("internal interpreter error"));
+}
+
+#[test]
+fn chdir_changes_cwd() {
+	let dir = std::env::temp_dir().join(format!("ulesl-cli-{}/chdir", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let dir = dir.canonicalize().unwrap();
+
+	let output = run_script(
+		"chdir",
+		"chdir(args()[0]);\nprintln(cwd());",
+		&[&dir.to_string_lossy()],
+	);
+
+	assert!(output.status.success(), "{output:?}");
+	assert_eq!(stdout(&output), format!("{}\n", dir.to_string_lossy()));
+}